@@ -1,8 +1,11 @@
 use super::migrator::account_map::AccountMap;
 use crate::fire_fly::FireFly;
 use crate::up_bank::UpBank;
-use color_eyre::eyre::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::vec;
@@ -12,16 +15,194 @@ fn default_time_between_imports() -> i64 {
     12
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn default_up_bank_base_url() -> String {
+    "https://api.up.com.au/api/v1".to_string()
+}
+
+fn default_boost_revenue_account() -> String {
+    "Up Bank Boost".to_string()
+}
+
+fn default_cashback_revenue_account() -> String {
+    "Up Bank Cashback".to_string()
+}
+
+fn default_rules_file() -> String {
+    "./config/rules.yaml".to_string()
+}
+
+fn default_webhook_listen_address() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+/// What to do with a Firefly transaction when its Up Bank transaction has been deleted, usually
+/// because a pending hold was cancelled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionPolicy {
+    #[default]
+    Ignore,
+    Delete,
+    Void,
+}
+
+/// How the boosted portion of a round up is imported when round up splits are enabled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundUpBoost {
+    /// A deposit into the round up Saver paid from the boost revenue account.
+    Deposit,
+    /// A transfer from the spending account, like the round up itself, Up Bank takes the boost
+    /// from the spending account so this keeps both balances in agreement.
+    #[default]
+    Transfer,
+}
+
+/// How Up Bank transactions that are still HELD (not yet settled) are imported.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HeldTransactionPolicy {
+    /// Wait until the transaction settles before importing it.
+    Skip,
+    /// Import straight away tagged as pending, the tag is removed once it settles.
+    Pending,
+    /// Import straight away and update the amount and dates once it settles.
+    #[default]
+    Import,
+}
+
+/// Where the Up Bank parent category of a transaction (eg home, transport) is recorded in Firefly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ParentCategoryTarget {
+    /// Parent categories are not recorded.
+    #[default]
+    None,
+    /// The transaction is assigned to the budget named after the parent category.
+    Budget,
+    /// The transaction is tagged with the name of the parent category.
+    Tag,
+}
+
+/// Which Firefly date the time an Up Bank transaction settled is recorded as.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SettledDateField {
+    #[default]
+    None,
+    BookDate,
+    ProcessDate,
+}
+
+/// Timeouts, retries and circuit breaker settings shared by the Up Bank and Firefly clients.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub timeout_seconds: u64,
+    pub connect_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_seconds: u64,
+    /// Requests that fail, after retrying, this many times in a row open the circuit breaker, 0
+    /// disables it.
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_cooldown_seconds: u64,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 30,
+            connect_timeout_seconds: 10,
+            max_retries: 4,
+            initial_backoff_ms: 500,
+            max_backoff_seconds: 30,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_cooldown_seconds: 300,
+        }
+    }
+}
+
+fn default_account_enabled() -> bool {
+    true
+}
+
+/// How a single Up Bank account is imported, set in the accounts section of the settings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountSettings {
+    #[serde(rename = "ff_account_id")]
+    pub fire_fly_account_id: String,
+    #[serde(default = "default_account_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub import_start_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A canonical payee name and the regular expressions that match the raw Up Bank names it covers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayeeAlias {
+    pub name: String,
+    pub patterns: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(rename = "upbank_pan")]
     pub up_pan_token: String,
+    #[serde(rename = "upbank_url", default = "default_up_bank_base_url")]
+    pub up_bank_base_url: String,
+    #[serde(rename = "upbank_webhook_secret", default)]
+    pub up_webhook_secret: Option<String>,
     #[serde(rename = "ff_pan")]
     pub fire_fly_pan_token: String,
     #[serde(rename = "ff_url")]
     pub fire_fly_base_url: String,
+    #[serde(rename = "ff_ca_cert", default)]
+    pub fire_fly_ca_certificate: Option<String>,
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountSettings>, // Up Bank account id to Firefly account
     #[serde(default = "default_time_between_imports")]
     pub time_between_imports: i64, // In hours
+    #[serde(default = "default_webhook_listen_address")]
+    pub webhook_listen_address: String,
+    #[serde(default)]
+    pub deleted_transactions: DeletionPolicy,
+    #[serde(default)]
+    pub held_transactions: HeldTransactionPolicy,
+    #[serde(default)]
+    pub round_up_splits: bool,
+    #[serde(default)]
+    pub round_up_account: Option<String>, // Up Bank ID of the Saver round ups are sent to
+    #[serde(default)]
+    pub round_up_boost: RoundUpBoost,
+    #[serde(default = "default_boost_revenue_account")]
+    pub boost_revenue_account: String, // Firefly revenue account boost deposits are paid from
+    #[serde(default)]
+    pub cashback_deposits: bool,
+    #[serde(default = "default_cashback_revenue_account")]
+    pub cashback_revenue_account: String, // Firefly revenue account cashback is paid from
+    #[serde(default)]
+    pub category_mapping: HashMap<String, String>, // Up Bank category or parent id to Firefly category name or id
+    #[serde(default)]
+    pub create_missing_categories: bool,
+    #[serde(default)]
+    pub parent_categories: ParentCategoryTarget,
+    #[serde(default)]
+    pub parent_category_mapping: HashMap<String, String>, // Up Bank parent id to Firefly budget or tag
+    #[serde(default = "default_rules_file")]
+    pub rules_file: String,
+    #[serde(default)]
+    pub normalise_payees: bool,
+    #[serde(default)]
+    pub payee_aliases: Vec<PayeeAlias>,
+    #[serde(default)]
+    pub notes_template: Option<String>,
+    #[serde(default)]
+    pub settled_date: SettledDateField,
+    #[serde(default)]
+    pub http: HttpSettings,
 }
 
 impl Config {
@@ -38,10 +219,51 @@ impl Config {
         up_bank: &UpBank,
         fire_fly: &FireFly,
     ) -> Result<Vec<AccountMap>> {
+        for up_account_id in self.accounts.keys() {
+            if !up_bank.accounts.iter().any(|f| &f.id == up_account_id) {
+                return Err(eyre!(
+                    "Up Bank account ({}) in the accounts section of settings.yaml does not exist in Up Bank",
+                    up_account_id
+                ));
+            }
+        }
+
         let mut account_vector: Vec<AccountMap> = vec![];
         for up_account in &up_bank.accounts {
             let up_account_id = up_account.id.as_str();
 
+            // Accounts listed in the settings take precedence over searching Firefly.
+            if let Some(settings) = self.accounts.get(up_account_id) {
+                if !settings.enabled {
+                    info!(
+                        "Up Bank account ({}) is disabled in settings.yaml, skipping",
+                        up_account_id
+                    );
+                    continue;
+                }
+                let fire_fly_account = fire_fly
+                    .get_account(&settings.fire_fly_account_id)
+                    .await
+                    .map_err(|e| {
+                        eyre!(
+                            "Firefly account ({}) mapped to Up Bank account ({}) could not be found, error: {}",
+                            settings.fire_fly_account_id,
+                            up_account_id,
+                            e
+                        )
+                    })?;
+                debug!(
+                    "Up ID {} mapped to Firefly ID {} in settings.yaml",
+                    up_account_id, fire_fly_account.id
+                );
+                account_vector.push(AccountMap {
+                    import_start_date: settings.import_start_date,
+                    tags: settings.tags.clone(),
+                    ..AccountMap::create(up_account_id.to_string(), fire_fly_account.id)
+                });
+                continue;
+            }
+
             // Attempt to find unique up bank id in firefly
             match fire_fly
                 .get_account_by_account_number(up_account_id)
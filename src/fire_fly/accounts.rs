@@ -1,8 +1,9 @@
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 use super::general::Meta;
 
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AccountsResponse {
     #[serde(default)]
@@ -26,8 +27,8 @@ pub struct Account {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Attributes {
-    pub created_at: String,
-    pub updated_at: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
     pub active: bool,
     pub order: Option<i64>,
     pub name: String,
@@ -38,15 +39,15 @@ pub struct Attributes {
     pub currency_code: String,
     pub currency_symbol: String,
     pub currency_decimal_places: i64,
-    pub current_balance: String,
-    pub current_balance_date: String,
+    pub current_balance: Decimal,
+    pub current_balance_date: Option<DateTime<FixedOffset>>,
     pub iban: Option<String>,
     pub bic: Option<String>,
     pub account_number: Option<String>,
-    pub opening_balance: Option<String>,
-    pub current_debt: Option<String>,
-    pub opening_balance_date: Option<String>,
-    pub virtual_balance: String,
+    pub opening_balance: Option<Decimal>,
+    pub current_debt: Option<Decimal>,
+    pub opening_balance_date: Option<DateTime<FixedOffset>>,
+    pub virtual_balance: Option<Decimal>,
     pub include_net_worth: Option<bool>,
     pub credit_card_type: Option<String>,
     pub monthly_payment_date: Option<String>,
@@ -59,3 +60,77 @@ pub struct Attributes {
     pub longitude: Option<f64>,
     pub zoom_level: Option<i64>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccountPayload {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub account_type: String,
+    pub account_role: Option<String>,
+    pub currency_code: Option<String>,
+    pub account_number: Option<String>,
+    pub opening_balance: Option<Decimal>,
+    pub opening_balance_date: Option<NaiveDate>,
+    pub include_net_worth: Option<bool>,
+    pub notes: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_balances_and_dates() {
+        let response: AccountResponse = serde_json::from_value(serde_json::json!({
+            "data": {
+                "type": "accounts",
+                "id": "1",
+                "attributes": {
+                    "created_at": "2023-01-01T10:00:00+10:00",
+                    "updated_at": "2023-02-01T10:00:00+10:00",
+                    "active": true,
+                    "order": 1,
+                    "name": "Spending",
+                    "type": "asset",
+                    "account_role": "defaultAsset",
+                    "currency_id": "1",
+                    "currency_code": "AUD",
+                    "currency_symbol": "$",
+                    "currency_decimal_places": 2,
+                    "current_balance": "123.45",
+                    "current_balance_date": "2023-02-01T23:59:59+10:00",
+                    "iban": null,
+                    "bic": null,
+                    "account_number": "acc1",
+                    "opening_balance": "-100.000000000000",
+                    "current_debt": null,
+                    "opening_balance_date": "2023-01-01T00:00:00+10:00",
+                    "virtual_balance": "0.00",
+                    "include_net_worth": true,
+                    "credit_card_type": null,
+                    "monthly_payment_date": null,
+                    "liability_type": null,
+                    "liability_direction": null,
+                    "interest": null,
+                    "interest_period": null,
+                    "notes": null,
+                    "latitude": null,
+                    "longitude": null,
+                    "zoom_level": null
+                }
+            }
+        }))
+        .unwrap();
+
+        let attributes = response.data.unwrap().attributes;
+        assert_eq!(attributes.current_balance, Decimal::new(12345, 2));
+        assert_eq!(attributes.opening_balance, Some(Decimal::new(-100, 0)));
+        assert_eq!(attributes.virtual_balance, Some(Decimal::ZERO));
+        assert_eq!(attributes.current_debt, None);
+        assert_eq!(
+            attributes.opening_balance_date.map(|f| f.date_naive()),
+            NaiveDate::from_ymd_opt(2023, 1, 1)
+        );
+        assert!(attributes.updated_at > attributes.created_at);
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use super::general::Meta;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoriesResponse {
+    #[serde(default)]
+    pub data: Vec<Category>,
+    pub meta: Option<Meta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryResponse {
+    pub data: Category,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Category {
+    #[serde(rename = "type")]
+    pub category_type: String,
+    pub id: String,
+    pub attributes: Attributes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attributes {
+    pub name: String,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryPayload {
+    pub name: String,
+    pub notes: Option<String>,
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use super::general::Meta;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrenciesResponse {
+    #[serde(default)]
+    pub data: Vec<Currency>,
+    pub meta: Option<Meta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrencyResponse {
+    pub data: Currency,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Currency {
+    #[serde(rename = "type")]
+    pub currency_type: String,
+    pub id: String,
+    pub attributes: Attributes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attributes {
+    pub code: String,
+    pub name: String,
+    pub symbol: String,
+    pub enabled: bool,
+    pub decimal_places: Option<i64>,
+}
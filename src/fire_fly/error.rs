@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::http_client::{ErrorKind, SendError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Why a request to Firefly failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The access token was rejected or has expired.
+    Unauthorized,
+    NotFound(String),
+    /// Firefly rejected the payload, along with the messages it gave for each invalid field.
+    Validation {
+        message: String,
+        errors: BTreeMap<String, Vec<String>>,
+    },
+    /// Still rate limited after retrying.
+    RateLimited,
+    /// More than one resource matched a lookup that should be unique.
+    Ambiguous(String),
+    /// Any other unsuccessful response.
+    Status {
+        status: StatusCode,
+        message: String,
+    },
+    /// Firefly could not be reached.
+    Transport(SendError),
+    /// The response could not be understood.
+    Decode(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Unauthorized => ErrorKind::Unauthorized,
+            Self::NotFound(_) => ErrorKind::NotFound,
+            Self::Validation { .. } => ErrorKind::Validation,
+            Self::RateLimited => ErrorKind::RateLimited,
+            Self::Ambiguous(_) | Self::Status { .. } => ErrorKind::Other,
+            Self::Transport(_) => ErrorKind::Transport,
+            Self::Decode(_) => ErrorKind::Decode,
+        }
+    }
+
+    /// See [`ErrorKind::is_fatal`].
+    pub fn is_fatal(&self) -> bool {
+        self.kind().is_fatal()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "Firefly rejected the access token"),
+            Self::NotFound(resource) => write!(f, "Firefly {resource} not found"),
+            Self::Validation { message, errors } => {
+                write!(f, "Firefly rejected the request: {message}")?;
+                for (field, field_errors) in errors {
+                    write!(f, ", {field}: {}", field_errors.join(" "))?;
+                }
+                Ok(())
+            }
+            Self::RateLimited => write!(f, "Firefly is rate limiting requests"),
+            Self::Ambiguous(lookup) => write!(f, "Firefly returned more than one {lookup}"),
+            Self::Status { status, message } => {
+                write!(f, "Firefly returned {status}: {message}")
+            }
+            Self::Transport(e) => write!(f, "Firefly request failed: {e}"),
+            Self::Decode(e) => write!(f, "Firefly response could not be decoded: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Self::Decode(e.to_string())
+        } else {
+            Self::Transport(SendError::Request(e))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ErrorBody {
+    message: Option<String>,
+    #[serde(default)]
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+/// Returns the response when it was successful, otherwise the error it describes.
+pub(super) async fn check_response(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let path = response.url().path().to_string();
+
+    let text = response.text().await?;
+    let body: ErrorBody = serde_json::from_str(&text).unwrap_or_default();
+    let message = body.message.unwrap_or(text);
+    Err(match ErrorKind::of_status(status) {
+        ErrorKind::Unauthorized => Error::Unauthorized,
+        ErrorKind::NotFound => Error::NotFound(path),
+        ErrorKind::Validation => Error::Validation {
+            message,
+            errors: body.errors,
+        },
+        ErrorKind::RateLimited => Error::RateLimited,
+        _ => Error::Status { status, message },
+    })
+}
+
+/// Checks the response and decodes its body.
+pub(super) async fn decode<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T> {
+    Ok(check_response(response).await?.json::<T>().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, body: &str) -> reqwest::Response {
+        axum::http::Response::builder()
+            .status(status)
+            .body(body.to_string())
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn validation_errors_are_read_from_the_body() {
+        let body = r#"{
+            "message": "The given data was invalid.",
+            "errors": {
+                "transactions.0.amount": ["The amount field is required."],
+                "transactions.0.source_id": [
+                    "This value is invalid for this field.",
+                    "Could not find a valid source account."
+                ]
+            }
+        }"#;
+
+        let error = check_response(response(422, body)).await.unwrap_err();
+
+        let Error::Validation { message, errors } = &error else {
+            panic!("expected a validation error, got {error:?}");
+        };
+        assert_eq!(message, "The given data was invalid.");
+        assert_eq!(
+            errors["transactions.0.amount"],
+            ["The amount field is required."]
+        );
+        assert_eq!(errors["transactions.0.source_id"].len(), 2);
+        assert!(!error.is_fatal());
+        assert_eq!(
+            error.to_string(),
+            "Firefly rejected the request: The given data was invalid., transactions.0.amount: \
+             The amount field is required., transactions.0.source_id: This value is invalid for \
+             this field. Could not find a valid source account."
+        );
+    }
+
+    #[tokio::test]
+    async fn statuses_are_classified() {
+        let error = check_response(response(401, r#"{"message":"Unauthenticated."}"#))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Unauthorized));
+        assert!(error.is_fatal());
+
+        let error = check_response(response(429, "")).await.unwrap_err();
+        assert!(matches!(error, Error::RateLimited));
+        assert!(error.is_fatal());
+
+        // Bodies that are not JSON are reported as they are.
+        let error = check_response(response(500, "Whoops")).await.unwrap_err();
+        assert!(
+            matches!(&error, Error::Status { status, message } if status.as_u16() == 500 && message == "Whoops")
+        );
+        assert!(!error.is_fatal());
+
+        let ok = check_response(response(200, "{}")).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,293 @@
+//! An in-memory Firefly for tests, serving the parts of the API the importer uses. Transaction
+//! groups are kept as JSON so tests can seed and inspect them through the client models.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{Method, StatusCode, Uri},
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Value};
+
+use super::{
+    transaction::{TransactionData, TransactionInsertRequest, TransactionPayload},
+    FireFly,
+};
+use crate::config::HttpSettings;
+
+#[derive(Default)]
+struct Data {
+    next_id: u64,
+    groups: BTreeMap<u64, Value>,
+    enabled_currencies: Vec<String>,
+    writes: Vec<String>,
+}
+
+type Shared = Arc<Mutex<Data>>;
+
+pub struct FakeFireFly {
+    pub url: String,
+    data: Shared,
+}
+
+impl FakeFireFly {
+    /// Starts the server on a random local port, must be called from inside a tokio runtime.
+    pub fn start() -> Self {
+        let data = Shared::default();
+        let app = Router::new()
+            .route("/api/v1/search/transactions", get(search))
+            .route("/api/v1/transactions", axum::routing::post(create))
+            .route(
+                "/api/v1/transactions/:id",
+                get(show).put(update).delete(delete),
+            )
+            .route("/api/v1/currencies", get(currencies))
+            .route(
+                "/api/v1/currencies/:code/enable",
+                axum::routing::post(enable_currency),
+            )
+            .route("/api/v1/categories", get(categories))
+            .with_state(data.clone());
+
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        Self { url, data }
+    }
+
+    /// A client for the server that does not retry, so failures show up straight away.
+    pub fn client(&self) -> FireFly {
+        let settings = HttpSettings {
+            max_retries: 0,
+            circuit_breaker_threshold: 0,
+            ..Default::default()
+        };
+        FireFly::create("token".to_string(), self.url.clone(), None, &settings).unwrap()
+    }
+
+    /// Adds a transaction group as though it had been imported earlier, returns its id.
+    pub fn add_group(&self, transactions: &[TransactionPayload]) -> String {
+        let mut data = self.data.lock().unwrap();
+        let transactions = serde_json::to_value(transactions).unwrap();
+        insert_group(&mut data, "", transactions.as_array().unwrap())
+    }
+
+    pub fn groups(&self) -> Vec<TransactionData> {
+        let data = self.data.lock().unwrap();
+        data.groups
+            .values()
+            .map(|f| serde_json::from_value(f.clone()).unwrap())
+            .collect()
+    }
+
+    /// Every request that changed something, eg `PUT /api/v1/transactions/1`.
+    pub fn writes(&self) -> Vec<String> {
+        self.data.lock().unwrap().writes.clone()
+    }
+}
+
+fn insert_group(data: &mut Data, group_title: &str, transactions: &[Value]) -> String {
+    data.next_id += 1;
+    let id = data.next_id;
+    let journals: Vec<Value> = transactions
+        .iter()
+        .enumerate()
+        .map(|(index, f)| journal(&format!("{}", id * 10 + index as u64), f))
+        .collect();
+    data.groups.insert(
+        id,
+        json!({
+            "type": "transactions",
+            "id": id.to_string(),
+            "attributes": {
+                "created_at": "2023-02-01T10:00:00+10:00",
+                "updated_at": "2023-02-01T10:00:00+10:00",
+                "user": "1",
+                "group_title": group_title,
+                "transactions": journals
+            },
+            "links": {
+                "0": { "rel": "self", "uri": format!("/transactions/{id}") },
+                "self": format!("http://firefly/api/v1/transactions/{id}")
+            }
+        }),
+    );
+    id.to_string()
+}
+
+/// Fills in the fields Firefly adds to a submitted split.
+fn journal(journal_id: &str, payload: &Value) -> Value {
+    let mut journal = json!({
+        "user": "1",
+        "transaction_journal_id": journal_id,
+        "currency_symbol": "$",
+        "currency_name": "Australian dollar",
+        "currency_decimal_places": 2,
+        "source_type": "Asset account",
+        "destination_type": "Expense account",
+        "reconciled": false,
+        "has_attachments": false,
+    });
+    merge(&mut journal, payload);
+    journal
+}
+
+fn merge(target: &mut Value, changes: &Value) {
+    if let (Some(target), Some(changes)) = (target.as_object_mut(), changes.as_object()) {
+        for (key, value) in changes {
+            target.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+fn record(data: &Shared, method: &Method, uri: &Uri) {
+    data.lock()
+        .unwrap()
+        .writes
+        .push(format!("{method} {}", uri.path()));
+}
+
+fn not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "message": "Resource not found", "exception": "NotFoundHttpException" })),
+    )
+}
+
+async fn search(
+    State(data): State<Shared>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let external_id = query
+        .get("query")
+        .and_then(|f| f.strip_prefix("external_id_is:"))
+        .unwrap_or_default();
+    let data = data.lock().unwrap();
+    let groups: Vec<&Value> = data
+        .groups
+        .values()
+        .filter(|f| {
+            f["attributes"]["transactions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|e| e["external_id"] == external_id)
+        })
+        .collect();
+    Json(json!({ "data": groups }))
+}
+
+async fn create(
+    State(data): State<Shared>,
+    method: Method,
+    uri: Uri,
+    Json(request): Json<TransactionInsertRequest>,
+) -> Json<Value> {
+    record(&data, &method, &uri);
+    let transactions = serde_json::to_value(&request.transactions).unwrap();
+    let mut data = data.lock().unwrap();
+    let id = insert_group(
+        &mut data,
+        &request.group_title,
+        transactions.as_array().unwrap(),
+    );
+    Json(json!({ "data": data.groups[&id.parse::<u64>().unwrap()] }))
+}
+
+async fn show(
+    State(data): State<Shared>,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let data = data.lock().unwrap();
+    let group = data.groups.get(&id).ok_or_else(not_found)?;
+    Ok(Json(json!({ "data": group })))
+}
+
+/// Changes the given fields of each journal, matched by journal id, like Firefly does.
+async fn update(
+    State(data): State<Shared>,
+    Path(id): Path<u64>,
+    method: Method,
+    uri: Uri,
+    Json(request): Json<Value>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    record(&data, &method, &uri);
+    let mut data = data.lock().unwrap();
+    let group = data.groups.get_mut(&id).ok_or_else(not_found)?;
+    for changes in request["transactions"].as_array().unwrap() {
+        let journals = group["attributes"]["transactions"].as_array_mut().unwrap();
+        if let Some(journal) = journals
+            .iter_mut()
+            .find(|f| f["transaction_journal_id"] == changes["transaction_journal_id"])
+        {
+            merge(journal, changes);
+        }
+    }
+    Ok(Json(json!({ "data": group })))
+}
+
+async fn delete(
+    State(data): State<Shared>,
+    Path(id): Path<u64>,
+    method: Method,
+    uri: Uri,
+) -> Result<StatusCode, (StatusCode, Json<Value>)> {
+    record(&data, &method, &uri);
+    let mut data = data.lock().unwrap();
+    data.groups.remove(&id).ok_or_else(not_found)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// AUD is enabled, USD exists but is disabled until it is enabled and nothing else exists.
+fn currency(id: &str, code: &str, enabled: bool) -> Value {
+    json!({
+        "type": "currencies",
+        "id": id,
+        "attributes": {
+            "code": code,
+            "name": code,
+            "symbol": "$",
+            "enabled": enabled,
+            "decimal_places": 2
+        }
+    })
+}
+
+async fn currencies(State(data): State<Shared>) -> Json<Value> {
+    let data = data.lock().unwrap();
+    let usd_enabled = data.enabled_currencies.iter().any(|f| f == "USD");
+    Json(json!({
+        "data": [currency("1", "AUD", true), currency("2", "USD", usd_enabled)],
+        "meta": {
+            "pagination": {
+                "total": 2, "count": 2, "per_page": 50, "current_page": 1, "total_pages": 1
+            }
+        }
+    }))
+}
+
+async fn enable_currency(
+    State(data): State<Shared>,
+    Path(code): Path<String>,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    record(&data, &method, &uri);
+    if code != "USD" {
+        return Err(not_found());
+    }
+    data.lock().unwrap().enabled_currencies.push(code.clone());
+    Ok(Json(json!({ "data": currency("2", &code, true) })))
+}
+
+async fn categories() -> Json<Value> {
+    Json(json!({ "data": [] }))
+}
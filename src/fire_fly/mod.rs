@@ -1,31 +1,107 @@
-use color_eyre::eyre::{eyre, Result};
+use chrono::NaiveDate;
+use color_eyre::eyre::eyre;
 use reqwest::header;
 use tracing::debug;
 
-use self::accounts::{Account, AccountResponse, AccountsResponse};
+use self::error::{check_response, decode, Error, Result};
+
+use crate::{config::HttpSettings, http_client::HttpClient};
+
+use self::accounts::{Account, AccountPayload, AccountResponse, AccountsResponse};
 
 pub mod accounts;
+pub mod categories;
+pub mod currencies;
+pub mod error;
+#[cfg(test)]
+pub mod fake;
 pub mod general;
 pub mod transaction;
 
 #[derive(Debug, Clone)]
 pub struct FireFly {
-    client: reqwest::Client,
+    client: HttpClient,
     base_url: String,
 }
 
 fn generate_url(base: &str, path: &str) -> String {
-    format!("http://{base}/api/v1/{path}")
+    format!("{base}api/v1/{path}")
+}
+
+/// Validates the Firefly url and normalises it so that it always ends in a `/`, urls without a
+/// scheme are treated as plain http to stay compatible with older settings files.
+fn parse_base_url(base_url: &str) -> color_eyre::Result<String> {
+    let base_url = if base_url.contains("://") {
+        base_url.to_string()
+    } else {
+        format!("http://{base_url}")
+    };
+
+    let mut url = reqwest::Url::parse(&base_url)
+        .map_err(|e| eyre!("Firefly base url ({}) is not valid, error: {}", base_url, e))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(eyre!(
+            "Firefly base url ({}) must use http or https, found: {}",
+            base_url,
+            url.scheme()
+        ));
+    }
+    if url.host_str().is_none() {
+        return Err(eyre!("Firefly base url ({}) is missing a host", base_url));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(eyre!(
+            "Firefly base url ({}) should not contain a query or fragment",
+            base_url
+        ));
+    }
+
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+
+    Ok(url.to_string())
+}
+
+/// Loads every certificate out of a PEM bundle so that Firefly instances signed by a private CA
+/// can be trusted.
+fn load_ca_certificates(path: &str) -> color_eyre::Result<Vec<reqwest::Certificate>> {
+    let bundle = std::fs::read_to_string(path)
+        .map_err(|e| eyre!("Failed to read CA bundle ({}), error: {}", path, e))?;
+
+    let end_marker = "-----END CERTIFICATE-----";
+    let certificates = bundle
+        .split_inclusive(end_marker)
+        .filter(|block| block.contains(end_marker))
+        .map(|block| reqwest::Certificate::from_pem(block.trim().as_bytes()))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if certificates.is_empty() {
+        return Err(eyre!(
+            "CA bundle ({}) did not contain any certificates",
+            path
+        ));
+    }
+    Ok(certificates)
 }
 
 impl FireFly {
-    pub fn create(access_token: String, base_url: String) -> Result<Self> {
+    pub fn create(
+        access_token: String,
+        base_url: String,
+        ca_certificate_path: Option<String>,
+        http_settings: &HttpSettings,
+    ) -> color_eyre::Result<Self> {
         if access_token.is_empty() {
             return Err(eyre!("Firefly access token was empty"));
         }
         if base_url.is_empty() {
             return Err(eyre!("Firefly base url was empty"));
         }
+        let base_url = parse_base_url(&base_url)?;
+        debug!("Firefly base url set to: {}", base_url);
 
         let access_token = format!("Bearer {access_token}",);
         let mut auth_value = header::HeaderValue::from_str(access_token.as_str())?;
@@ -44,53 +120,78 @@ impl FireFly {
 
         headers.insert("Authorization", auth_value);
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
+        let mut client_builder = reqwest::Client::builder().default_headers(headers);
+        if let Some(path) = ca_certificate_path {
+            for certificate in load_ca_certificates(&path)? {
+                client_builder = client_builder.add_root_certificate(certificate);
+            }
+            debug!("Loaded custom CA bundle from: {}", path);
+        }
+
+        let client = HttpClient::create("Firefly", client_builder, http_settings)?;
 
         Ok(Self { client, base_url })
     }
 
+    /// How long until requests are sent to Firefly again after repeated failures.
+    pub fn circuit_open_for(&self) -> Option<std::time::Duration> {
+        self.client.circuit_open_for()
+    }
+
     pub async fn get_all_accounts(&self) -> Result<Vec<Account>> {
-        let accounts = self
+        let response = self
             .client
             .get(generate_url(&self.base_url, "accounts"))
             .send()
-            .await?
-            .json::<AccountsResponse>()
-            .await?
-            .data;
+            .await?;
 
-        Ok(accounts)
+        Ok(decode::<AccountsResponse>(response).await?.data)
     }
 
     pub async fn get_account(&self, id: &str) -> Result<Account> {
-        let account = self
+        let response = self
             .client
             .get(generate_url(&self.base_url, &format!("accounts/{id}")))
             .send()
+            .await?;
+
+        decode::<AccountResponse>(response)
             .await?
-            .json::<AccountResponse>()
+            .data
+            .ok_or_else(|| Error::NotFound(format!("account ({id})")))
+    }
+
+    pub async fn create_account(&self, account: &AccountPayload) -> Result<Account> {
+        let response = self
+            .client
+            .post(generate_url(&self.base_url, "accounts"))
+            .json(account)
+            .send()
+            .await?;
+
+        decode::<AccountResponse>(response)
             .await?
             .data
-            .ok_or_else(|| eyre!("Firefly ID({}), account not found.", id))?;
-        Ok(account)
+            .ok_or_else(|| {
+                Error::Decode(format!(
+                    "the created account ({}) was not returned",
+                    account.name
+                ))
+            })
     }
 
     pub async fn get_account_by_account_number(&self, id: &str) -> Result<Option<Account>> {
         let mut url_address = generate_url(&self.base_url, "search/accounts");
         url_address = format!("{}?query={}&type=all&field=number", url_address, id);
-        let accounts = self
-            .client
-            .get(url_address)
-            .send()
-            .await?
-            .json::<AccountsResponse>()
-            .await?
-            .data;
+        let response = self.client.get(url_address).send().await?;
+        let accounts = decode::<AccountsResponse>(response).await?.data;
 
         if accounts.len() > 1 {
-            return Err(eyre!("When trying to find a unique account by account id, {} accounts were found, should have been 1", accounts.len()));
+            return Err(Error::Ambiguous(format!(
+                "account with the account number ({}), {} were found",
+                id,
+                accounts.len()
+            )));
         }
 
         let account = accounts.into_iter().next();
@@ -98,6 +199,86 @@ impl FireFly {
         Ok(account)
     }
 
+    pub async fn get_all_categories(&self) -> Result<Vec<categories::Category>> {
+        let mut categories: Vec<categories::Category> = vec![];
+        let mut page = 1;
+
+        loop {
+            let url_address = format!(
+                "{}?page={}",
+                generate_url(&self.base_url, "categories"),
+                page
+            );
+            let response = self.client.get(url_address).send().await?;
+            let mut category_data = decode::<categories::CategoriesResponse>(response).await?;
+
+            categories.append(&mut category_data.data);
+
+            match category_data.meta {
+                Some(meta) if meta.pagination.current_page < meta.pagination.total_pages => {
+                    page += 1
+                }
+                _ => break,
+            }
+        }
+
+        Ok(categories)
+    }
+
+    pub async fn create_category(&self, name: &str) -> Result<categories::Category> {
+        let payload = categories::CategoryPayload {
+            name: name.to_string(),
+            notes: None,
+        };
+        let response = self
+            .client
+            .post(generate_url(&self.base_url, "categories"))
+            .json(&payload)
+            .send()
+            .await?;
+
+        Ok(decode::<categories::CategoryResponse>(response).await?.data)
+    }
+
+    pub async fn get_all_currencies(&self) -> Result<Vec<currencies::Currency>> {
+        let mut currencies: Vec<currencies::Currency> = vec![];
+        let mut page = 1;
+
+        loop {
+            let url_address = format!(
+                "{}?page={}",
+                generate_url(&self.base_url, "currencies"),
+                page
+            );
+            let response = self.client.get(url_address).send().await?;
+            let mut currency_data = decode::<currencies::CurrenciesResponse>(response).await?;
+
+            currencies.append(&mut currency_data.data);
+
+            match currency_data.meta {
+                Some(meta) if meta.pagination.current_page < meta.pagination.total_pages => {
+                    page += 1
+                }
+                _ => break,
+            }
+        }
+
+        Ok(currencies)
+    }
+
+    pub async fn enable_currency(&self, code: &str) -> Result<currencies::Currency> {
+        let response = self
+            .client
+            .post(generate_url(
+                &self.base_url,
+                &format!("currencies/{code}/enable"),
+            ))
+            .send()
+            .await?;
+
+        Ok(decode::<currencies::CurrencyResponse>(response).await?.data)
+    }
+
     pub async fn find_transaction_by_external_id(
         &self,
         id: &str,
@@ -105,13 +286,8 @@ impl FireFly {
         let mut url_address = generate_url(&self.base_url, "search/transactions");
         url_address = format!("{}?query=external_id_is%3A{}", url_address, id);
 
-        let transactions = self
-            .client
-            .get(url_address)
-            .send()
-            .await?
-            .json::<transaction::TransactionSearchRequest>()
-            .await?;
+        let response = self.client.get(url_address).send().await?;
+        let transactions = decode::<transaction::TransactionSearchRequest>(response).await?;
         debug!(
             "Transaction ({}) returned {} enteries",
             id,
@@ -120,47 +296,242 @@ impl FireFly {
         Ok(transactions.data)
     }
 
+    /// Fetches every transaction group touching an account, optionally limited to a date range.
+    pub async fn get_account_transactions(
+        &self,
+        id: &str,
+        start_date: Option<NaiveDate>,
+        end_date: Option<NaiveDate>,
+    ) -> Result<Vec<transaction::TransactionData>> {
+        let mut transactions: Vec<transaction::TransactionData> = vec![];
+        let mut params: Vec<(String, String)> = vec![];
+        if let Some(date) = start_date {
+            params.push(("start".to_string(), date.format("%Y-%m-%d").to_string()));
+        }
+        if let Some(date) = end_date {
+            params.push(("end".to_string(), date.format("%Y-%m-%d").to_string()));
+        }
+        let mut page = 1;
+
+        loop {
+            let response = self
+                .client
+                .get(generate_url(
+                    &self.base_url,
+                    &format!("accounts/{id}/transactions"),
+                ))
+                .query(&params)
+                .query(&[("page", page)])
+                .send()
+                .await?;
+            let mut transaction_data =
+                decode::<transaction::TransactionSearchRequest>(response).await?;
+
+            transactions.append(&mut transaction_data.data);
+
+            match transaction_data.meta {
+                Some(meta) if meta.pagination.current_page < meta.pagination.total_pages => {
+                    page += 1
+                }
+                _ => break,
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Submits a new transaction and returns the journal id Firefly assigned to it.
     pub async fn submit_new_transaction(
         &self,
         transaction: &transaction::TransactionPayload,
-    ) -> Result<()> {
-        let mut payload = transaction::TransactionInsertRequest {
+    ) -> Result<String> {
+        let group = self
+            .submit_transaction_group("", std::slice::from_ref(transaction))
+            .await?;
+        Ok(group.attributes.transactions[0]
+            .transaction_journal_id
+            .clone())
+    }
+
+    /// Submits one or more splits as a single transaction group and returns the group Firefly
+    /// created, its journals are in the same order as the splits. Firefly requires every split in
+    /// a group to share the same transaction type.
+    pub async fn submit_transaction_group(
+        &self,
+        group_title: &str,
+        transactions: &[transaction::TransactionPayload],
+    ) -> Result<transaction::TransactionData> {
+        let payload = transaction::TransactionInsertRequest {
             error_if_duplicate_hash: false,
             apply_rules: true,
             fire_webhooks: true,
-            group_title: "".to_string(),
-            transactions: Vec::new(),
+            group_title: group_title.to_string(),
+            transactions: transactions.to_vec(),
         };
-        payload.transactions.push(transaction.clone());
         let response = self
             .client
             .post(generate_url(&self.base_url, "transactions"))
             .json(&payload)
             .send()
             .await?;
-        let status_code = response.status();
-        if status_code != 200 {
-            let error_info = response.text().await?;
-            return Err(eyre!(
-                "Failed to submit transaction({:?}), error code: {}, error: {}",
-                transaction,
-                status_code,
-                error_info
+        let group = decode::<transaction::TransactionResponse>(response)
+            .await?
+            .data;
+        if group.attributes.transactions.is_empty() {
+            return Err(Error::Decode(
+                "the created transaction was not returned".to_string(),
             ));
         }
+        Ok(group)
+    }
+
+    pub async fn get_transaction_group(&self, id: &str) -> Result<transaction::TransactionData> {
+        let response = self
+            .client
+            .get(generate_url(&self.base_url, &format!("transactions/{id}")))
+            .send()
+            .await?;
+        Ok(decode::<transaction::TransactionResponse>(response)
+            .await?
+            .data)
+    }
+
+    /// Replaces a journal of a transaction group with the given one.
+    pub async fn update_transaction(
+        &self,
+        group_id: &str,
+        transaction: transaction::Transaction,
+    ) -> Result<()> {
+        let payload = transaction::TransactionUpdateRequest {
+            apply_rules: true,
+            fire_webhooks: true,
+            transactions: vec![transaction],
+        };
+        let response = self
+            .client
+            .put(generate_url(
+                &self.base_url,
+                &format!("transactions/{group_id}"),
+            ))
+            .json(&payload)
+            .send()
+            .await?;
+
+        check_response(response).await?;
         Ok(())
     }
 
-    pub async fn update_transaction(&self, transaction: transaction::Transaction) -> Result<()> {
-        let address = format!("transactions/{}", transaction.transaction_journal_id);
-        let url_address = generate_url(&self.base_url, &address);
+    /// Marks a journal as reconciled, leaving the rest of the transaction untouched.
+    pub async fn mark_reconciled(&self, group_id: &str, journal_id: &str) -> Result<()> {
+        let payload = transaction::ReconcileRequest {
+            apply_rules: false,
+            fire_webhooks: false,
+            transactions: vec![transaction::ReconcilePayload {
+                transaction_journal_id: journal_id.to_string(),
+                reconciled: true,
+            }],
+        };
+        let response = self
+            .client
+            .put(generate_url(
+                &self.base_url,
+                &format!("transactions/{group_id}"),
+            ))
+            .json(&payload)
+            .send()
+            .await?;
 
-        self.client
-            .put(url_address)
-            .json(&transaction)
+        check_response(response).await?;
+        Ok(())
+    }
+
+    /// Deletes a transaction group and every journal inside of it.
+    pub async fn delete_transaction_group(&self, id: &str) -> Result<()> {
+        let response = self
+            .client
+            .delete(generate_url(&self.base_url, &format!("transactions/{id}")))
             .send()
             .await?;
 
+        check_response(response).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_url_is_normalised() {
+        for (base_url, expected) in [
+            ("localhost:8080", "http://localhost:8080/"),
+            ("http://firefly.local", "http://firefly.local/"),
+            (
+                "https://example.com/firefly",
+                "https://example.com/firefly/",
+            ),
+            (
+                "https://example.com/firefly/",
+                "https://example.com/firefly/",
+            ),
+        ] {
+            assert_eq!(parse_base_url(base_url).unwrap(), expected, "{base_url}");
+        }
+        assert_eq!(
+            generate_url(
+                &parse_base_url("https://example.com/firefly").unwrap(),
+                "accounts"
+            ),
+            "https://example.com/firefly/api/v1/accounts"
+        );
+    }
+
+    #[test]
+    fn invalid_base_urls_are_rejected() {
+        for base_url in [
+            "ftp://example.com",
+            "https://example.com/?token=1",
+            "https://example.com/#top",
+            "http://",
+            "https://exa mple.com",
+        ] {
+            assert!(parse_base_url(base_url).is_err(), "{base_url}");
+        }
+    }
+
+    #[test]
+    fn ca_bundle_must_exist_and_contain_certificates() {
+        let error = load_ca_certificates("./does/not/exist.pem").unwrap_err();
+        assert!(error.to_string().contains("Failed to read CA bundle"));
+
+        let path = std::env::temp_dir().join(format!("empty-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        let error = load_ca_certificates(path.to_str().unwrap()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error
+            .to_string()
+            .contains("did not contain any certificates"));
+    }
+
+    #[test]
+    fn create_checks_the_settings() {
+        let settings = HttpSettings::default();
+        assert!(FireFly::create(String::new(), "localhost".to_string(), None, &settings).is_err());
+        assert!(FireFly::create("token".to_string(), String::new(), None, &settings).is_err());
+        assert!(FireFly::create(
+            "token".to_string(),
+            "localhost".to_string(),
+            Some("./does/not/exist.pem".to_string()),
+            &settings
+        )
+        .is_err());
+        assert!(FireFly::create(
+            "token".to_string(),
+            "localhost".to_string(),
+            None,
+            &settings
+        )
+        .is_ok());
+    }
+}
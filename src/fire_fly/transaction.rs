@@ -1,9 +1,19 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+use super::general::Meta;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransactionSearchRequest {
     #[serde(default)]
     pub data: Vec<TransactionData>,
+    pub meta: Option<Meta>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionResponse {
+    pub data: TransactionData,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,20 +27,20 @@ pub struct TransactionData {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Attributes {
-    pub created_at: String,
-    pub updated_at: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
     pub user: String,
     pub group_title: Option<String>,
     pub transactions: Vec<Transaction>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Transaction {
     pub user: String,
     pub transaction_journal_id: String,
     #[serde(rename = "type")]
     pub transaction_type: String,
-    pub date: String,
+    pub date: DateTime<FixedOffset>,
     pub order: Option<i32>,
     pub currency_id: Option<String>,
     pub currency_code: Option<String>,
@@ -41,8 +51,8 @@ pub struct Transaction {
     pub foreign_currency_code: Option<String>,
     pub foreign_currency_symbol: Option<String>,
     pub foreign_currency_decimal_places: Option<i32>,
-    pub amount: String,
-    pub foreign_amount: Option<String>,
+    pub amount: Decimal,
+    pub foreign_amount: Option<Decimal>,
     pub description: String,
     pub source_id: Option<String>,
     pub source_name: Option<String>,
@@ -79,12 +89,12 @@ pub struct Transaction {
     pub sepa_ep: Option<String>,
     pub sepa_ci: Option<String>,
     pub sepa_batch_id: Option<String>,
-    pub interest_date: Option<String>,
-    pub book_date: Option<String>,
-    pub process_date: Option<String>,
-    pub due_date: Option<String>,
-    pub payment_date: Option<String>,
-    pub invoice_date: Option<String>,
+    pub interest_date: Option<DateTime<FixedOffset>>,
+    pub book_date: Option<DateTime<FixedOffset>>,
+    pub process_date: Option<DateTime<FixedOffset>>,
+    pub due_date: Option<DateTime<FixedOffset>>,
+    pub payment_date: Option<DateTime<FixedOffset>>,
+    pub invoice_date: Option<DateTime<FixedOffset>>,
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
     pub zoom_level: Option<i64>,
@@ -115,20 +125,43 @@ pub struct TransactionInsertRequest {
     pub transactions: Vec<TransactionPayload>,
 }
 
+/// Updates journals of an existing transaction group, each identified by its journal id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionUpdateRequest {
+    pub apply_rules: bool,
+    pub fire_webhooks: bool,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Updates only the reconciled flag of the given journals in a transaction group.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconcileRequest {
+    pub apply_rules: bool,
+    pub fire_webhooks: bool,
+    pub transactions: Vec<ReconcilePayload>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconcilePayload {
+    pub transaction_journal_id: String,
+    pub reconciled: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct TransactionPayload {
     #[serde(rename = "type")]
     pub transaction_type: String,
-    pub date: String,
-    pub amount: String,
+    pub date: DateTime<FixedOffset>,
+    pub amount: Decimal,
     pub description: String,
     pub order: Option<i32>,
     pub currency_id: Option<String>,
     pub currency_code: Option<String>,
-    pub foreign_amount: Option<String>,
+    pub foreign_amount: Option<Decimal>,
     pub foreign_currency_id: Option<String>,
     pub foreign_currency_code: Option<String>,
     pub budget_id: Option<String>,
+    pub budget_name: Option<String>,
     pub category_id: Option<String>,
     pub category_name: Option<String>,
     pub source_id: Option<String>,
@@ -155,10 +188,10 @@ pub struct TransactionPayload {
     pub sepa_ep: Option<String>,
     pub sepa_ci: Option<String>,
     pub sepa_batch_id: Option<String>,
-    pub interest_date: Option<String>,
-    pub book_date: Option<String>,
-    pub process_date: Option<String>,
-    pub due_date: Option<String>,
-    pub payment_date: Option<String>,
-    pub invoice_date: Option<String>,
+    pub interest_date: Option<DateTime<FixedOffset>>,
+    pub book_date: Option<DateTime<FixedOffset>>,
+    pub process_date: Option<DateTime<FixedOffset>>,
+    pub due_date: Option<DateTime<FixedOffset>>,
+    pub payment_date: Option<DateTime<FixedOffset>>,
+    pub invoice_date: Option<DateTime<FixedOffset>>,
 }
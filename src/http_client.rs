@@ -0,0 +1,482 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use rand::Rng;
+use reqwest::{header, Method, StatusCode};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::config::HttpSettings;
+
+/// Stops requests from being sent to a service that keeps failing, so a continuous import does not
+/// hammer an API that is down. Once the cooldown has passed requests are let through again, the
+/// breaker closes when one succeeds and reopens as soon as one fails.
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// A reqwest client that retries transient failures with exponential backoff and jitter, honours
+/// `429` responses and `Retry-After` (up to the maximum backoff), and trips a circuit breaker after repeated failures.
+///
+/// Connection errors, `429` and `503` are retried for every request, other `5xx` responses and
+/// timeouts are only retried for requests that are safe to repeat (everything but `POST`) so a
+/// transaction is never created twice.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    name: &'static str,
+    client: reqwest::Client,
+    settings: HttpSettings,
+    breaker: Arc<Mutex<CircuitBreaker>>,
+}
+
+/// A request built through [`HttpClient`], sent with retries.
+pub struct Request<'a> {
+    client: &'a HttpClient,
+    method: Method,
+    builder: reqwest::RequestBuilder,
+}
+
+/// Why a request could not be sent or no response was received.
+#[derive(Debug)]
+pub enum SendError {
+    /// The circuit breaker is open, requests resume after the given time.
+    Unavailable(Duration),
+    Request(reqwest::Error),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable(remaining) => write!(
+                f,
+                "unavailable after repeated failures, requests resume in {remaining:?}"
+            ),
+            Self::Request(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unavailable(_) => None,
+            Self::Request(e) => Some(e),
+        }
+    }
+}
+
+/// What went wrong behind an error from either API, so Firefly and Up Bank classify responses
+/// and decide which errors stop an import the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Unauthorized,
+    NotFound,
+    /// The request was understood but its content was rejected.
+    Validation,
+    RateLimited,
+    /// Any other unsuccessful response, or a response that does not make sense.
+    Other,
+    Transport,
+    Decode,
+}
+
+impl ErrorKind {
+    /// The kind of error an unsuccessful response status describes.
+    pub fn of_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Unauthorized,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::UNPROCESSABLE_ENTITY => Self::Validation,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            _ => Self::Other,
+        }
+    }
+
+    /// True when every following request is bound to fail as well, so an import should stop
+    /// instead of recording the error against a single transaction and moving on.
+    pub fn is_fatal(self) -> bool {
+        matches!(
+            self,
+            Self::Unauthorized | Self::RateLimited | Self::Transport
+        )
+    }
+}
+
+enum Failure {
+    Response(reqwest::Response),
+    Error(reqwest::Error),
+}
+
+impl HttpClient {
+    /// Builds the client, `name` identifies the service in logs and errors.
+    pub fn create(
+        name: &'static str,
+        builder: reqwest::ClientBuilder,
+        settings: &HttpSettings,
+    ) -> Result<Self> {
+        let client = builder
+            .timeout(Duration::from_secs(settings.timeout_seconds))
+            .connect_timeout(Duration::from_secs(settings.connect_timeout_seconds))
+            .build()?;
+
+        Ok(Self {
+            name,
+            client,
+            settings: settings.clone(),
+            breaker: Arc::new(Mutex::new(CircuitBreaker::default())),
+        })
+    }
+
+    pub fn get<U: reqwest::IntoUrl>(&self, url: U) -> Request<'_> {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U: reqwest::IntoUrl>(&self, url: U) -> Request<'_> {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put<U: reqwest::IntoUrl>(&self, url: U) -> Request<'_> {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn delete<U: reqwest::IntoUrl>(&self, url: U) -> Request<'_> {
+        self.request(Method::DELETE, url)
+    }
+
+    fn request<U: reqwest::IntoUrl>(&self, method: Method, url: U) -> Request<'_> {
+        Request {
+            client: self,
+            method: method.clone(),
+            builder: self.client.request(method, url),
+        }
+    }
+
+    /// How long until the circuit breaker lets requests through again, None when it is closed.
+    pub fn circuit_open_for(&self) -> Option<Duration> {
+        let breaker = self.breaker.lock().unwrap();
+        breaker
+            .open_until
+            .and_then(|f| f.checked_duration_since(Instant::now()))
+    }
+
+    fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.open_until.is_some() {
+            debug!(
+                "{} is reachable again, closing the circuit breaker",
+                self.name
+            );
+        }
+        *breaker = CircuitBreaker::default();
+    }
+
+    fn record_failure(&self) {
+        let threshold = self.settings.circuit_breaker_threshold;
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.consecutive_failures += 1;
+        if threshold > 0 && breaker.consecutive_failures >= threshold {
+            let cooldown = Duration::from_secs(self.settings.circuit_breaker_cooldown_seconds);
+            warn!(
+                "{} failed {} requests in a row, pausing requests for {:?}",
+                self.name, breaker.consecutive_failures, cooldown
+            );
+            breaker.open_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    /// The longest the client waits before a retry, also caps what `Retry-After` can ask for so a
+    /// misbehaving server cannot stall an import.
+    fn max_backoff(&self) -> Duration {
+        Duration::from_secs(self.settings.max_backoff_seconds)
+    }
+
+    /// The delay before the given retry, doubling each attempt up to the maximum and randomised
+    /// between half and all of it so clients do not retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let initial = Duration::from_millis(self.settings.initial_backoff_ms);
+        let maximum = self.max_backoff();
+        let delay = initial
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(maximum)
+            .min(maximum);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+impl<'a> Request<'a> {
+    pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+        self.builder = self.builder.query(query);
+        self
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, json: &T) -> Self {
+        self.builder = self.builder.json(json);
+        self
+    }
+
+    pub async fn send(self) -> std::result::Result<reqwest::Response, SendError> {
+        let client = self.client;
+        if let Some(remaining) = client.circuit_open_for() {
+            return Err(SendError::Unavailable(remaining));
+        }
+
+        let is_idempotent = self.method != Method::POST;
+        let mut attempt = 0;
+        loop {
+            let request = match self.builder.try_clone() {
+                Some(request) => request,
+                // Streaming bodies can only be sent once.
+                None => return self.builder.send().await.map_err(SendError::Request),
+            };
+
+            let failure = match request.send().await {
+                Ok(response) if is_retryable_status(response.status(), is_idempotent) => {
+                    Failure::Response(response)
+                }
+                Ok(response) => {
+                    // A server error that is not retried still counts towards the breaker.
+                    if response.status().is_server_error() {
+                        client.record_failure();
+                    } else {
+                        client.record_success();
+                    }
+                    return Ok(response);
+                }
+                Err(e) if e.is_connect() || (e.is_timeout() && is_idempotent) => Failure::Error(e),
+                Err(e) => {
+                    client.record_failure();
+                    return Err(SendError::Request(e));
+                }
+            };
+
+            if attempt >= client.settings.max_retries {
+                client.record_failure();
+                return match failure {
+                    // Handed back so the caller reports the status and body as usual.
+                    Failure::Response(response) => Ok(response),
+                    Failure::Error(e) => Err(SendError::Request(e)),
+                };
+            }
+
+            let (reason, delay) = match &failure {
+                Failure::Response(response) => (
+                    format!("status {}", response.status()),
+                    retry_after(response)
+                        .map(|f| f.min(client.max_backoff()))
+                        .unwrap_or_else(|| client.backoff(attempt)),
+                ),
+                Failure::Error(e) => (e.to_string(), client.backoff(attempt)),
+            };
+            attempt += 1;
+            warn!(
+                "{} {} request failed ({}), retrying in {:?} (attempt {} of {})",
+                client.name, self.method, reason, delay, attempt, client.settings.max_retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode, is_idempotent: bool) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => true,
+        status => status.is_server_error() && is_idempotent,
+    }
+}
+
+/// Reads the `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+
+    use axum::{extract::State, http::HeaderMap, routing::any, Router};
+
+    use super::*;
+
+    /// A status to respond with and the `Retry-After` header to send with it.
+    type Reply = (u16, Option<&'static str>);
+
+    /// Responds with the given statuses and `Retry-After` values in order, then with `200`.
+    #[derive(Clone, Default)]
+    struct Script {
+        responses: Arc<Mutex<VecDeque<Reply>>>,
+        requests: Arc<Mutex<u32>>,
+    }
+
+    impl Script {
+        fn requests(&self) -> u32 {
+            *self.requests.lock().unwrap()
+        }
+    }
+
+    async fn respond(State(script): State<Script>) -> (axum::http::StatusCode, HeaderMap) {
+        *script.requests.lock().unwrap() += 1;
+        let (status, retry_after) = script
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or((200, None));
+        let mut headers = HeaderMap::new();
+        if let Some(retry_after) = retry_after {
+            headers.insert(header::RETRY_AFTER, retry_after.parse().unwrap());
+        }
+        (axum::http::StatusCode::from_u16(status).unwrap(), headers)
+    }
+
+    fn serve(responses: &[Reply]) -> (String, Script) {
+        let script = Script {
+            responses: Arc::new(Mutex::new(responses.iter().copied().collect())),
+            ..Default::default()
+        };
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/", any(respond))
+            .with_state(script.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, script)
+    }
+
+    fn client(settings: HttpSettings) -> HttpClient {
+        HttpClient::create("Test", reqwest::Client::builder(), &settings).unwrap()
+    }
+
+    fn fast_retries() -> HttpSettings {
+        HttpSettings {
+            max_retries: 2,
+            initial_backoff_ms: 1,
+            max_backoff_seconds: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_for_get() {
+        let (url, script) = serve(&[(500, None), (502, None)]);
+
+        let response = client(fast_retries()).get(&url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(script.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_retried_for_post() {
+        let (url, script) = serve(&[(500, None)]);
+
+        let response = client(fast_retries()).post(&url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(script.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_waits_for_retry_after() {
+        let (url, script) = serve(&[(429, Some("1"))]);
+        let started = Instant::now();
+
+        let response = client(HttpSettings {
+            max_backoff_seconds: 5,
+            ..fast_retries()
+        })
+        .post(&url)
+        .send()
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(script.requests(), 2);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_capped_at_the_maximum_backoff() {
+        let (url, script) = serve(&[(503, Some("3600"))]);
+        let started = Instant::now();
+
+        let response = client(fast_retries()).get(&url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(script.requests(), 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_opens_and_closes() {
+        let (url, script) = serve(&[(500, None), (500, None), (500, None)]);
+        let client = client(HttpSettings {
+            max_retries: 0,
+            circuit_breaker_threshold: 2,
+            circuit_breaker_cooldown_seconds: 1,
+            ..fast_retries()
+        });
+
+        // Two failures in a row open the breaker and stop requests reaching the server.
+        for _ in 0..2 {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert!(client.circuit_open_for().is_some());
+        assert!(matches!(
+            client.get(&url).send().await,
+            Err(SendError::Unavailable(_))
+        ));
+        assert_eq!(script.requests(), 2);
+
+        // A failure once the cooldown has passed opens it again straight away.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        client.get(&url).send().await.unwrap();
+        assert!(client.circuit_open_for().is_some());
+
+        // A success once the cooldown has passed closes it.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(client.circuit_open_for(), None);
+        assert_eq!(script.requests(), 4);
+    }
+
+    #[tokio::test]
+    async fn server_errors_that_are_not_retried_open_the_circuit_breaker() {
+        let (url, script) = serve(&[(500, None), (500, None)]);
+        let client = client(HttpSettings {
+            circuit_breaker_threshold: 2,
+            circuit_breaker_cooldown_seconds: 60,
+            ..fast_retries()
+        });
+
+        for _ in 0..2 {
+            let response = client.post(&url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert!(client.circuit_open_for().is_some());
+        assert!(matches!(
+            client.post(&url).send().await,
+            Err(SendError::Unavailable(_))
+        ));
+        assert_eq!(script.requests(), 2);
+    }
+}
@@ -1,19 +1,33 @@
 pub mod config;
 pub mod fire_fly;
+pub mod http_client;
 pub mod migrator;
 pub mod operation;
+pub mod reconcile;
+pub mod state_store;
 pub mod up_bank;
+pub mod webhook;
 use clap::{Parser, ValueEnum};
 use color_eyre::eyre::Result;
 use tracing::info;
 
-use config::Config;
+use config::{Config, DeletionPolicy};
 
 #[derive(Parser, Debug, Clone, ValueEnum)]
 enum Action {
     Import,
     GetAccountInfo,
     ContinuesImport,
+    Rehash,
+    Serve,
+    WebhookCreate,
+    WebhookList,
+    WebhookPing,
+    WebhookDelete,
+    WebhookLogs,
+    RulesTest,
+    LinkAccounts,
+    Reconcile,
 }
 
 #[derive(Parser, Debug)]
@@ -28,6 +42,39 @@ pub struct Args {
     date_range: Option<i64>,
     #[clap(env, value_enum, default_value_t = Action::Import)]
     action: Action,
+    /// Print what would be created or updated in Firefly without changing anything
+    #[clap(env, long, action)]
+    dry_run: bool,
+    /// Url Up Bank should deliver events to, used by webhook-create
+    #[clap(env, long, value_parser)]
+    webhook_url: Option<String>,
+    /// Description of the webhook, used by webhook-create
+    #[clap(env, long, value_parser)]
+    webhook_description: Option<String>,
+    /// Up Bank webhook ID, used by webhook-ping, webhook-delete and webhook-logs
+    #[clap(env, long, value_parser)]
+    webhook_id: Option<String>,
+    /// Overrides how transactions deleted in Up Bank are handled for this run
+    #[clap(env, long, value_enum)]
+    deletion_policy: Option<DeletionPolicy>,
+    /// Answer yes to every prompt, used by link-accounts
+    #[clap(env, long, action)]
+    yes: bool,
+    /// Mark settled transactions of accounts whose balances agree as reconciled, used by reconcile
+    #[clap(env, long, action)]
+    mark_reconciled: bool,
+    /// Book any difference left before the date range as a Firefly reconciliation, used by reconcile
+    #[clap(env, long, action)]
+    reconcile_residual: bool,
+    /// Upgrade legacy hashes without checking that the transaction is unchanged, used by rehash
+    #[clap(env, long, action)]
+    assume_unchanged: bool,
+    /// Up Bank transaction ID, used by rules-test
+    #[clap(env, long, value_parser)]
+    transaction_id: Option<String>,
+    /// Overrides the Up Bank API url set in settings.yaml
+    #[clap(env, long, value_parser)]
+    up_bank_url: Option<String>,
 }
 
 #[tokio::main]
@@ -36,15 +83,27 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     info!("Starting services");
-    let config = Config::load("./config/settings.yaml")?;
+    let mut config = Config::load("./config/settings.yaml")?;
     info!("Loaded config file");
     let args = Args::parse();
+    if let Some(up_bank_url) = &args.up_bank_url {
+        config.up_bank_base_url = up_bank_url.clone();
+    }
+    if let Some(deletion_policy) = args.deletion_policy {
+        config.deleted_transactions = deletion_policy;
+    }
     info!("Parsed arguments and updated config");
 
-    let mut up_bank = up_bank::UpBank::create(config.up_pan_token.clone())?;
+    let mut up_bank = up_bank::UpBank::create(
+        config.up_pan_token.clone(),
+        config.up_bank_base_url.clone(),
+        &config.http,
+    )?;
     let fire_fly = fire_fly::FireFly::create(
         config.fire_fly_pan_token.clone(),
         config.fire_fly_base_url.clone(),
+        config.fire_fly_ca_certificate.clone(),
+        &config.http,
     )?;
 
     info!("FireFly and UpBank api initilised");
@@ -58,6 +117,20 @@ async fn main() -> Result<()> {
         Action::ContinuesImport => {
             operation::continues_import(args, up_bank, fire_fly, config).await?
         }
+        Action::Rehash => operation::rehash_transactions(&args, &up_bank).await?,
+        Action::Serve => operation::serve_webhooks(&args, up_bank, fire_fly, &config).await?,
+        Action::WebhookCreate => operation::create_webhook(&args, &up_bank).await?,
+        Action::WebhookList => operation::list_webhooks(&up_bank).await?,
+        Action::WebhookPing => operation::ping_webhook(&args, &up_bank).await?,
+        Action::WebhookDelete => operation::delete_webhook(&args, &up_bank).await?,
+        Action::WebhookLogs => operation::print_webhook_logs(&args, &up_bank).await?,
+        Action::RulesTest => operation::test_rules(&args, &up_bank, &config).await?,
+        Action::Reconcile => {
+            operation::reconcile_accounts(&args, &up_bank, &fire_fly, &config).await?
+        }
+        Action::LinkAccounts => {
+            operation::link_accounts(&args, &up_bank, &fire_fly, &config).await?
+        }
     }
 
     Ok(())
@@ -1,13 +1,21 @@
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccountMap {
     pub up_account_id: String,
     pub fire_fly_account_id: String,
+    pub import_start_date: Option<NaiveDate>, // Transactions before this date are not imported
+    pub tags: Vec<String>, // Added to every transaction imported from the account
 }
 
 impl AccountMap {
     pub fn create(up_account_id: String, fire_fly_account_id: String) -> Self {
-        Self {up_account_id, fire_fly_account_id}
+        Self {
+            up_account_id,
+            fire_fly_account_id,
+            import_start_date: None,
+            tags: vec![],
+        }
     }
 }
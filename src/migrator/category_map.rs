@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::up_bank;
+
+/// A Firefly category, budget or account as written in the settings or rules file, values made up
+/// of only digits are treated as a Firefly id and anything else as a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FireFlyReference {
+    Id(String),
+    Name(String),
+}
+
+impl FireFlyReference {
+    pub fn parse(value: &str) -> Self {
+        if !value.is_empty() && value.chars().all(|f| f.is_ascii_digit()) {
+            Self::Id(value.to_string())
+        } else {
+            Self::Name(value.to_string())
+        }
+    }
+
+    /// Splits the reference into the `*_id` and `*_name` fields Firefly expects, only one of the
+    /// two is ever set so Firefly does not prefer a stale id over a new name.
+    pub fn into_fields(self) -> (Option<String>, Option<String>) {
+        match self {
+            Self::Id(id) => (Some(id), None),
+            Self::Name(name) => (None, Some(name)),
+        }
+    }
+}
+
+/// Decides which Firefly category an Up Bank transaction is filed under.
+pub struct CategoryMap {
+    mapping: HashMap<String, FireFlyReference>,
+    parent_mapping: HashMap<String, String>,
+    up_bank_names: HashMap<String, String>,
+    // Up Bank category id to the id of its parent, transactions are only ever filed under these.
+    up_bank_parents: HashMap<String, String>,
+}
+
+impl CategoryMap {
+    pub fn create(
+        mapping: &HashMap<String, String>,
+        parent_mapping: &HashMap<String, String>,
+        up_bank_categories: &[up_bank::categories::Categorie],
+    ) -> Self {
+        Self {
+            mapping: mapping
+                .iter()
+                .map(|(up_bank_id, category)| {
+                    (up_bank_id.clone(), FireFlyReference::parse(category))
+                })
+                .collect(),
+            parent_mapping: parent_mapping.clone(),
+            up_bank_names: up_bank_categories
+                .iter()
+                .map(|f| (f.id.clone(), f.attributes.name.clone()))
+                .collect(),
+            up_bank_parents: up_bank_categories
+                .iter()
+                .filter_map(|f| {
+                    Some((
+                        f.id.clone(),
+                        f.relationships.parent.data.as_ref()?.id.clone(),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    /// Resolves the category of a transaction, checking in order: the mapping for its category,
+    /// the mapping for its parent category, the Up Bank name of its category and lastly the raw
+    /// Up Bank category id.
+    pub fn resolve(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+    ) -> Option<FireFlyReference> {
+        let relationships = &up_bank_transaction.relationships;
+        let category_id = relationships.category.data.as_ref().map(|f| &f.id)?;
+        let parent_id = relationships.parent_category.data.as_ref().map(|f| &f.id);
+
+        if let Some(category) = self.mapping.get(category_id) {
+            return Some(category.clone());
+        }
+        if let Some(category) = parent_id.and_then(|f| self.mapping.get(f)) {
+            return Some(category.clone());
+        }
+        match self.up_bank_names.get(category_id) {
+            Some(name) => Some(FireFlyReference::Name(name.clone())),
+            None => Some(FireFlyReference::Name(category_id.replace('-', "_"))),
+        }
+    }
+
+    /// Resolves the name the parent category of a transaction is recorded under, either the
+    /// mapping for the parent category or its Up Bank name.
+    pub fn resolve_parent(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+    ) -> Option<String> {
+        let parent_id = up_bank_transaction
+            .relationships
+            .parent_category
+            .data
+            .as_ref()
+            .map(|f| &f.id)?;
+
+        if let Some(name) = self.parent_mapping.get(parent_id) {
+            return Some(name.clone());
+        }
+        Some(
+            self.up_bank_names
+                .get(parent_id)
+                .cloned()
+                .unwrap_or_else(|| parent_id.replace('-', "_")),
+        )
+    }
+
+    /// Every category name a transaction can be resolved to, sorted and without duplicates. Up
+    /// Bank names are only included for the categories transactions are filed under, and not
+    /// when the category or its parent is mapped to something else.
+    pub fn category_names(&self) -> Vec<String> {
+        let unmapped_names = self
+            .up_bank_parents
+            .iter()
+            .filter(|(id, parent_id)| {
+                !self.mapping.contains_key(*id) && !self.mapping.contains_key(*parent_id)
+            })
+            .filter_map(|(id, _)| self.up_bank_names.get(id).cloned());
+        let mut names: Vec<String> = self
+            .mapping
+            .values()
+            .filter_map(|f| match f {
+                FireFlyReference::Name(name) => Some(name.clone()),
+                FireFlyReference::Id(_) => None,
+            })
+            .chain(unmapped_names)
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::{categories::Categorie, transactions::fixtures::transaction};
+
+    fn category(id: &str, name: &str, parent: Option<&str>) -> Categorie {
+        serde_json::from_value(serde_json::json!({
+            "type": "categories",
+            "id": id,
+            "attributes": { "name": name },
+            "relationships": {
+                "parent": {
+                    "data": parent.map(|f| serde_json::json!({ "type": "categories", "id": f }))
+                },
+                "children": { "data": [] }
+            },
+            "links": { "self": null }
+        }))
+        .unwrap()
+    }
+
+    fn up_bank_categories() -> Vec<Categorie> {
+        vec![
+            category("good-life", "Good Life", None),
+            category(
+                "restaurants-and-cafes",
+                "Restaurants & Cafes",
+                Some("good-life"),
+            ),
+            category("booze", "Booze", Some("good-life")),
+            category("transport", "Transport", None),
+            category("fuel", "Fuel", Some("transport")),
+            category("parking", "Parking", Some("transport")),
+        ]
+    }
+
+    fn categorised(category_id: &str, parent_id: &str) -> up_bank::transactions::Transaction {
+        let mut purchase = transaction("tx1", "Cafe", "-4.50");
+        purchase.relationships.category.data = Some(up_bank::general::Data {
+            dat_type: "categories".to_string(),
+            id: category_id.to_string(),
+        });
+        purchase.relationships.parent_category.data = Some(up_bank::general::Data {
+            dat_type: "categories".to_string(),
+            id: parent_id.to_string(),
+        });
+        purchase
+    }
+
+    fn mapping(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn names_only_cover_categories_transactions_resolve_to() {
+        let category_map = CategoryMap::create(
+            &mapping(&[("booze", "Drinks"), ("transport", "Car"), ("parking", "12")]),
+            &HashMap::new(),
+            &up_bank_categories(),
+        );
+
+        // Parent categories, and categories mapped either directly or through their parent, are
+        // never resolved to their Up Bank name.
+        assert_eq!(
+            category_map.category_names(),
+            vec!["Car", "Drinks", "Restaurants & Cafes"]
+        );
+    }
+
+    #[test]
+    fn names_match_what_resolve_returns() {
+        let category_map = CategoryMap::create(
+            &mapping(&[("booze", "Drinks"), ("transport", "Car")]),
+            &HashMap::new(),
+            &up_bank_categories(),
+        );
+        let names = category_map.category_names();
+
+        for (category_id, parent_id) in [
+            ("restaurants-and-cafes", "good-life"),
+            ("booze", "good-life"),
+            ("fuel", "transport"),
+            ("parking", "transport"),
+        ] {
+            match category_map.resolve(&categorised(category_id, parent_id)) {
+                Some(FireFlyReference::Name(name)) => assert!(names.contains(&name), "{name}"),
+                other => panic!("{category_id} resolved to {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn mapped_id_is_not_created() {
+        let category_map = CategoryMap::create(
+            &mapping(&[("fuel", "12")]),
+            &HashMap::new(),
+            &up_bank_categories(),
+        );
+        assert_eq!(
+            category_map.resolve(&categorised("fuel", "transport")),
+            Some(FireFlyReference::Id("12".to_string()))
+        );
+        assert!(!category_map.category_names().contains(&"Fuel".to_string()));
+    }
+}
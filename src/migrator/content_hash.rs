@@ -0,0 +1,168 @@
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::up_bank::transactions::Transaction;
+
+/// Version of the hash produced by `calculate_hash`, stored next to every hash so that a change to
+/// the algorithm or the fields it covers can be detected and migrated without touching Firefly.
+///
+/// - 0: `DefaultHasher` over the whole transaction, including links (legacy, unstable).
+/// - 1: SHA-256 over the canonical fields listed in `canonical_fields`.
+pub const HASH_VERSION: i64 = 1;
+
+/// Hashes the fields of an Up Bank transaction that are imported into Firefly. The output is
+/// deterministic across platforms and toolchains and is returned as lowercase hex.
+pub fn calculate_hash(transaction: &Transaction) -> String {
+    let mut hasher = Sha256::new();
+    for (field, value) in canonical_fields(transaction) {
+        // Length prefixes keep `a=bc` and `ab=c` style values from colliding.
+        hasher.update(field.len().to_be_bytes());
+        hasher.update(field.as_bytes());
+        hasher.update(value.len().to_be_bytes());
+        hasher.update(value.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// The hash used before versioning was introduced, only kept so that transactions tracked by
+/// older versions can be verified and re-hashed.
+pub fn calculate_legacy_hash(transaction: &Transaction) -> String {
+    let mut s = DefaultHasher::new();
+    transaction.hash(&mut s);
+    s.finish().to_string()
+}
+
+/// The fields covered by the hash, in a fixed order. Adding or removing a field here, or changing
+/// how one is formatted, requires `HASH_VERSION` to be bumped. Amounts keep the scale and
+/// timestamps the RFC 3339 form Up Bank sends them in.
+fn canonical_fields(transaction: &Transaction) -> Vec<(&'static str, String)> {
+    let attributes = &transaction.attributes;
+    let relationships = &transaction.relationships;
+
+    let mut tags: Vec<&str> = relationships
+        .tags
+        .data
+        .iter()
+        .map(|f| f.id.as_str())
+        .collect();
+    tags.sort_unstable();
+
+    vec![
+        ("id", transaction.id.clone()),
+        ("status", attributes.status.as_str().to_string()),
+        ("amount.currency", attributes.amount.currency_code.clone()),
+        ("amount.value", attributes.amount.value.to_string()),
+        (
+            "foreign_amount.currency",
+            optional(attributes.foreign_amount.as_ref().map(|f| &f.currency_code)),
+        ),
+        (
+            "foreign_amount.value",
+            optional(
+                attributes
+                    .foreign_amount
+                    .as_ref()
+                    .map(|f| f.value.to_string())
+                    .as_ref(),
+            ),
+        ),
+        ("description", attributes.description.clone()),
+        ("raw_text", optional(attributes.raw_text.as_ref())),
+        ("message", optional(attributes.message.as_ref())),
+        (
+            "settled_at",
+            optional(attributes.settled_at.map(|f| f.to_rfc3339()).as_ref()),
+        ),
+        ("created_at", attributes.created_at.to_rfc3339()),
+        (
+            "account",
+            optional(relationships.account.data.as_ref().map(|f| &f.id)),
+        ),
+        (
+            "transfer_account",
+            optional(relationships.transfer_account.data.as_ref().map(|f| &f.id)),
+        ),
+        (
+            "category",
+            optional(relationships.category.data.as_ref().map(|f| &f.id)),
+        ),
+        (
+            "parent_category",
+            optional(relationships.parent_category.data.as_ref().map(|f| &f.id)),
+        ),
+        ("tags", tags.join(",")),
+    ]
+}
+
+fn optional(value: Option<&String>) -> String {
+    value.cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::{general::Data, transactions::fixtures::transaction};
+
+    // The hash is stored in the state store, so it must never change without `HASH_VERSION` being
+    // bumped. These values were produced by version 1 and must not be updated to make a test pass.
+    #[test]
+    fn purchase_matches_golden_hash() {
+        assert_eq!(
+            calculate_hash(&transaction("tx1", "Cafe", "-4.50")),
+            "3af19908432656fb151cbc96f3d58537e1859e349182016bc90582268076d75a"
+        );
+    }
+
+    #[test]
+    fn optional_fields_match_golden_hash() {
+        let mut purchase = transaction("tx2", "Woolworths", "-120.05");
+        purchase.attributes.message = Some("groceries".to_string());
+        purchase.attributes.raw_text = Some("WOOLWORTHS 1234 SYDNEY".to_string());
+        purchase.attributes.foreign_amount = Some(purchase.attributes.amount.clone());
+        for id in ["groceries", "food"] {
+            purchase.relationships.tags.data.push(Data {
+                dat_type: "tags".to_string(),
+                id: id.to_string(),
+            });
+        }
+        assert_eq!(
+            calculate_hash(&purchase),
+            "572a460ff848d893b9bacc85b310d737290a4ef2aad537083d9423b6afeabd59"
+        );
+    }
+
+    #[test]
+    fn tag_order_does_not_change_the_hash() {
+        let mut first = transaction("tx1", "Cafe", "-4.50");
+        let mut second = first.clone();
+        for (transaction, ids) in [(&mut first, ["a", "b"]), (&mut second, ["b", "a"])] {
+            for id in ids {
+                transaction.relationships.tags.data.push(Data {
+                    dat_type: "tags".to_string(),
+                    id: id.to_string(),
+                });
+            }
+        }
+        assert_eq!(calculate_hash(&first), calculate_hash(&second));
+    }
+
+    #[test]
+    fn amount_scale_changes_the_hash() {
+        assert_ne!(
+            calculate_hash(&transaction("tx1", "Cafe", "-4.50")),
+            calculate_hash(&transaction("tx1", "Cafe", "-4.5"))
+        );
+    }
+
+    #[test]
+    fn fields_do_not_run_together() {
+        let mut first = transaction("tx1", "Cafe", "-4.50");
+        let mut second = first.clone();
+        first.attributes.description = "ab".to_string();
+        first.attributes.raw_text = Some("c".to_string());
+        second.attributes.description = "a".to_string();
+        second.attributes.raw_text = Some("bc".to_string());
+        assert_ne!(calculate_hash(&first), calculate_hash(&second));
+    }
+}
@@ -1,25 +1,79 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    fire_fly, migrator::transaction_map::get_fire_fly_transction_from_up_bank_id, up_bank,
+    config::{Config, DeletionPolicy, HeldTransactionPolicy, ParentCategoryTarget},
+    fire_fly,
+    migrator::transaction_map::get_fire_fly_transction_from_up_bank_id,
+    up_bank,
 };
 
+use crate::up_bank::transactions::TransactionStatus;
+
 use self::{
     account_map::AccountMap,
-    transaction_tracker::{TransactionHashData, TransactionType},
+    category_map::{CategoryMap, FireFlyReference},
+    payee::PayeeNormaliser,
+    plan::Plan,
+    rules::Rules,
+    splits::{Split, SplitKind},
+    transaction_tracker::{FireFlyGroup, TransactionHashData, TransactionType},
 };
-use color_eyre::eyre::{eyre, Result};
-use tracing::{debug, error, info};
+use color_eyre::eyre::{eyre, Report, Result};
+use tracing::{debug, error, info, warn};
 
 pub mod account_map;
+pub mod category_map;
+pub mod content_hash;
+pub mod payee;
+pub mod plan;
+pub mod rules;
+pub mod splits;
 pub mod transaction_map;
 pub mod transaction_tracker;
 
+const IMPORT_TAG: &str = "UBFF3Import";
+const VOIDED_TAG: &str = "voided";
+const PENDING_TAG: &str = "pending";
+
+/// True when an error means the import cannot carry on, eg an expired access token or an API that
+/// cannot be reached, rather than a problem with a single transaction.
+fn is_fatal(error: &Report) -> bool {
+    error.chain().any(|f| {
+        f.downcast_ref::<fire_fly::error::Error>()
+            .map(|e| e.is_fatal())
+            .or_else(|| {
+                f.downcast_ref::<up_bank::error::Error>()
+                    .map(|e| e.is_fatal())
+            })
+            .unwrap_or(false)
+    })
+}
+
+enum ProcessOutcome {
+    New,
+    Updated,
+    UpdateFailed,
+    AlreadyImported,
+    Skipped,
+    Dropped,
+    /// Creating the transaction failed, the error has been recorded and it is retried on the next
+    /// import.
+    Failed,
+}
+
 pub struct Migrator {
     up_bank_api: up_bank::UpBank,
     fire_fly_api: fire_fly::FireFly,
     account_map: Vec<AccountMap>,
+    category_map: CategoryMap,
+    currencies: Option<HashMap<String, fire_fly::currencies::Currency>>,
+    rules: Rules,
+    payee_normaliser: PayeeNormaliser,
     transaction_tracker: TransactionHashData,
+    round_up_account: Option<AccountMap>,
+    config: Config,
+    dry_run: bool,
+    plan: Plan,
 }
 
 impl Migrator {
@@ -27,14 +81,56 @@ impl Migrator {
         up_bank_api: up_bank::UpBank,
         fire_fly_api: fire_fly::FireFly,
         account_map: Vec<AccountMap>,
-    ) -> Self {
-        let transaction_tracker = TransactionHashData::open();
-        Self {
+        config: &Config,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let round_up_account = if config.round_up_splits {
+            let up_account_id = config.round_up_account.as_ref().ok_or_else(|| {
+                eyre!("round_up_account must be set when round_up_splits is enabled")
+            })?;
+            let fire_fly_account_id =
+                transaction_map::is_account_internal(up_account_id, &account_map).ok_or_else(
+                    || {
+                        eyre!(
+                            "Round up account ({}) is not mapped to a Firefly account",
+                            up_account_id
+                        )
+                    },
+                )?;
+            Some(AccountMap::create(
+                up_account_id.clone(),
+                fire_fly_account_id,
+            ))
+        } else {
+            None
+        };
+
+        let category_map = CategoryMap::create(
+            &config.category_mapping,
+            &config.parent_category_mapping,
+            &up_bank_api.categories,
+        );
+
+        let rules = Rules::load(&config.rules_file)?;
+        let payee_normaliser =
+            PayeeNormaliser::create(&config.payee_aliases, config.normalise_payees)?;
+
+        // A dry run must leave the tracker file exactly as it was found.
+        let transaction_tracker = TransactionHashData::open(dry_run)?;
+        Ok(Self {
             up_bank_api,
             fire_fly_api,
             account_map,
+            category_map,
+            currencies: None,
+            rules,
+            payee_normaliser,
             transaction_tracker,
-        }
+            round_up_account,
+            config: config.clone(),
+            dry_run,
+            plan: Plan::default(),
+        })
     }
 
     pub async fn migrate_transactions(
@@ -42,60 +138,388 @@ impl Migrator {
         start_date: Option<chrono::naive::NaiveDate>,
         end_date: Option<chrono::naive::NaiveDate>,
     ) -> Result<()> {
+        // The same bounds are used to find deleted transactions, so only transactions Up Bank was
+        // asked for can be considered missing.
+        let (since, until) = up_bank::date_filter_bounds(start_date, end_date);
         let up_bank_transaction = self
             .up_bank_api
-            .get_all_transactions(start_date, end_date)
+            .get_transactions_between(since, until)
             .await?;
 
+        // Taken before filtering, a transaction that is not imported has not been deleted.
+        let fetched_ids: HashSet<String> =
+            up_bank_transaction.iter().map(|f| f.id.clone()).collect();
+
         let up_bank_transaction: Vec<up_bank::transactions::Transaction> = up_bank_transaction
             .into_iter()
-            .filter(|e| {
-                self.account_map
-                    .iter()
-                    .any(|f| f.up_account_id == e.relationships.account.data.as_ref().unwrap().id)
-            })
+            .filter(|e| self.is_account_mapped(e))
             .collect();
 
         info!("Processing {} transactions", up_bank_transaction.len());
         let mut not_found_counter = 0;
         let mut needs_update_counter = 0;
         let mut already_imported_counter = 0;
-
-        let tag: String = "UBFF3Import".to_string();
+        let mut skipped_counter = 0;
+        let mut dropped_counter = 0;
+        let mut failed_update_counter = 0;
+        let mut failed_counter = 0;
 
         for transaction in up_bank_transaction {
-            match self.transaction_tracker.find_transaction(&transaction) {
-                transaction_tracker::Status::NotFound => {
-                    if self.new_transaction(&transaction, &tag).await? {
-                        not_found_counter += 1;
-                    } else {
-                        // Since we do not already have a hash we wont know if it needs to be updated.
-                        already_imported_counter += 1;
-                        self.transaction_tracker
-                            .add_transaction(&transaction, TransactionType::Duplicate);
-                    }
-                }
-                transaction_tracker::Status::FoundExact => {
-                    debug!(
-                        "Transaction({}) found in TransactionMap with no update required, skipping",
-                        transaction.id
+            match self.process_transaction(&transaction).await {
+                Ok(ProcessOutcome::New) => not_found_counter += 1,
+                Ok(ProcessOutcome::Updated) => needs_update_counter += 1,
+                Ok(ProcessOutcome::UpdateFailed) => failed_update_counter += 1,
+                Ok(ProcessOutcome::AlreadyImported) => already_imported_counter += 1,
+                Ok(ProcessOutcome::Skipped) => skipped_counter += 1,
+                Ok(ProcessOutcome::Dropped) => dropped_counter += 1,
+                Ok(ProcessOutcome::Failed) => failed_counter += 1,
+                Err(e) if is_fatal(&e) => return Err(e),
+                Err(e) => {
+                    error!(
+                        "Transaction({}) failed to process, skipping, error: {:?}",
+                        transaction.id, e
                     );
-                    already_imported_counter += 1;
                     self.transaction_tracker
-                        .add_transaction(&transaction, TransactionType::Duplicate);
+                        .record_error(&transaction.id, &e.to_string())?;
+                    failed_counter += 1;
                 }
-                transaction_tracker::Status::FoundNotExact => {
-                    self.update_transaction(&transaction).await?;
-                    needs_update_counter += 1;
-                }
-            };
+            }
+        }
+
+        let deleted_counter = self
+            .remove_deleted_transactions(since, until, &fetched_ids)
+            .await?;
+
+        if self.dry_run {
+            self.plan.print();
+        }
+
+        info!("Import complete, {} new transactions, {} updated transactions, {} updates failed and will be retried, {} deleted transactions, {} held transactions skipped, {} dropped by rules, {} failed and {} were already imported and identical", not_found_counter, needs_update_counter, failed_update_counter, deleted_counter, skipped_counter, dropped_counter, failed_counter, already_imported_counter);
+
+        Ok(())
+    }
+
+    /// Imports or updates a single Up Bank transaction, used when a transaction is pushed to the
+    /// importer rather than fetched as part of a date range.
+    pub async fn migrate_single_transaction(
+        &mut self,
+        transaction: &up_bank::transactions::Transaction,
+    ) -> Result<()> {
+        if !self.is_account_mapped(transaction) {
+            debug!(
+                "Transaction({}) belongs to an account that is not mapped to Firefly or is before its import start date, skipping",
+                transaction.id
+            );
+            return Ok(());
+        }
+
+        match self.process_transaction(transaction).await? {
+            ProcessOutcome::New => info!("Transaction({}) imported", transaction.id),
+            ProcessOutcome::Updated => info!("Transaction({}) updated", transaction.id),
+            ProcessOutcome::UpdateFailed => warn!(
+                "Transaction({}) failed to update, it will be retried on the next import",
+                transaction.id
+            ),
+            ProcessOutcome::AlreadyImported => info!(
+                "Transaction({}) was already imported and identical",
+                transaction.id
+            ),
+            ProcessOutcome::Skipped => info!(
+                "Transaction({}) is held, it will be imported once it settles",
+                transaction.id
+            ),
+            ProcessOutcome::Dropped => {
+                info!("Transaction({}) was dropped by rules", transaction.id)
+            }
+            // Reported as an error so a webhook delivery is not acknowledged and arrives again.
+            ProcessOutcome::Failed => {
+                return Err(eyre!("Transaction({}) failed to import", transaction.id))
+            }
+        }
+
+        self.print_plan();
+        Ok(())
+    }
+
+    /// Prints and clears the changes collected during a dry run, does nothing otherwise.
+    pub fn print_plan(&mut self) {
+        if self.dry_run {
+            std::mem::take(&mut self.plan).print();
+        }
+    }
+
+    /// Creates every category transactions can be filed under that does not exist in Firefly yet,
+    /// when enabled in the settings. Called once before transactions are imported.
+    pub async fn create_missing_categories(&self) -> Result<()> {
+        if !self.config.create_missing_categories {
+            return Ok(());
         }
 
-        info!("Import complete, {} new transactions, {} updated transactions and {} were already imported and identical", not_found_counter, needs_update_counter, already_imported_counter);
+        let existing_names: HashSet<String> = self
+            .fire_fly_api
+            .get_all_categories()
+            .await?
+            .into_iter()
+            .map(|f| f.attributes.name.to_lowercase())
+            .collect();
 
+        for name in self.category_map.category_names() {
+            if existing_names.contains(&name.to_lowercase()) {
+                continue;
+            }
+            if self.dry_run {
+                info!(
+                    "Category({}) is missing in Firefly and would be created",
+                    name
+                );
+            } else {
+                let category = self.fire_fly_api.create_category(&name).await?;
+                info!(
+                    "Created Firefly category({}) with ID: {}",
+                    name, category.id
+                );
+            }
+        }
         Ok(())
     }
 
+    /// Finds tracked transactions inside the fetched date range that Up Bank no longer returned and
+    /// removes them from Firefly, returns how many were removed.
+    async fn remove_deleted_transactions(
+        &mut self,
+        since: Option<chrono::DateTime<chrono::Local>>,
+        until: Option<chrono::DateTime<chrono::Local>>,
+        fetched_ids: &HashSet<String>,
+    ) -> Result<usize> {
+        if self.config.deleted_transactions == DeletionPolicy::Ignore {
+            debug!("Deletion policy is ignore, skipping deleted transaction check");
+            return Ok(0);
+        }
+
+        let deleted_ids: Vec<String> =
+            self.transaction_tracker
+                .get_tracked_transactions()?
+                .into_iter()
+                .filter(|f| !fetched_ids.contains(&f.up_bank_id))
+                .filter(|f| {
+                    let Some(account) = f.up_bank_account_id.as_ref().and_then(|account_id| {
+                        self.account_map
+                            .iter()
+                            .find(|e| &e.up_account_id == account_id)
+                    }) else {
+                        return false;
+                    };
+                    // Only transactions strictly inside the window can be known to be missing, and
+                    // transactions from before the import start date of their account are no longer
+                    // looked at.
+                    match f.up_bank_created_at.as_ref().and_then(|created_at| {
+                        chrono::DateTime::parse_from_rfc3339(created_at).ok()
+                    }) {
+                        Some(created_at) => {
+                            since.map(|since| created_at > since).unwrap_or(true)
+                                && until.map(|until| created_at < until).unwrap_or(true)
+                                && account
+                                    .import_start_date
+                                    .map(|start_date| created_at.date_naive() >= start_date)
+                                    .unwrap_or(true)
+                        }
+                        None => false,
+                    }
+                })
+                .map(|f| f.up_bank_id)
+                .collect();
+
+        let mut deleted_counter = 0;
+        for up_bank_id in deleted_ids {
+            info!("Transaction({}) no longer exists in Up Bank", up_bank_id);
+            if self.remove_deleted_transaction(&up_bank_id).await? {
+                deleted_counter += 1;
+            }
+        }
+        Ok(deleted_counter)
+    }
+
+    /// Deletes or voids the Firefly transaction imported from an Up Bank transaction that has since
+    /// been deleted, depending on the configured deletion policy. Returns true when Firefly was
+    /// changed.
+    pub async fn remove_deleted_transaction(&mut self, up_bank_id: &str) -> Result<bool> {
+        let tracked_transaction = match self
+            .transaction_tracker
+            .get_tracked_transaction(up_bank_id)?
+        {
+            Some(tracked_transaction) => tracked_transaction,
+            None => {
+                debug!(
+                    "Deleted transaction({}) was never imported, nothing to remove",
+                    up_bank_id
+                );
+                return Ok(false);
+            }
+        };
+
+        let deletion_policy = self.config.deleted_transactions;
+        if deletion_policy == DeletionPolicy::Ignore {
+            warn!(
+                "Transaction({}) was deleted in Up Bank, deletion policy is ignore so Firefly was left unchanged",
+                up_bank_id
+            );
+            return Ok(false);
+        }
+
+        // The receiving side of an internal transfer and dropped transactions never created anything
+        // in Firefly.
+        if matches!(
+            tracked_transaction.transaction_type,
+            Some(TransactionType::TransferDuplicate | TransactionType::Dropped)
+        ) {
+            self.transaction_tracker.mark_deleted(up_bank_id)?;
+            return Ok(false);
+        }
+
+        let mut fire_fly_transactions = self
+            .fire_fly_api
+            .find_transaction_by_external_id(up_bank_id)
+            .await?;
+        // Groups created alongside the purchase, eg its round up, have external ids of their own.
+        for group in self.transaction_tracker.get_groups(up_bank_id)? {
+            if group.split == SplitKind::Purchase {
+                continue;
+            }
+            if let Some(fire_fly_transaction) = self.get_group(up_bank_id, &group).await? {
+                fire_fly_transactions.push(fire_fly_transaction);
+            }
+        }
+
+        for fire_fly_transaction in fire_fly_transactions {
+            match deletion_policy {
+                DeletionPolicy::Delete => {
+                    if self.dry_run {
+                        self.plan.add_delete(up_bank_id, &fire_fly_transaction.id);
+                    } else {
+                        self.fire_fly_api
+                            .delete_transaction_group(&fire_fly_transaction.id)
+                            .await?;
+                        info!(
+                            "Deleted Firefly transaction group({}) for Up Bank transaction({})",
+                            fire_fly_transaction.id, up_bank_id
+                        );
+                    }
+                }
+                DeletionPolicy::Void => {
+                    for journal in fire_fly_transaction.attributes.transactions {
+                        let mut voided_journal = journal.clone();
+                        voided_journal.tags.push(VOIDED_TAG.to_string());
+                        dedup(&mut voided_journal.tags);
+                        if self.dry_run {
+                            self.plan.add_update(up_bank_id, &journal, &voided_journal);
+                        } else {
+                            self.fire_fly_api
+                                .update_transaction(&fire_fly_transaction.id, voided_journal)
+                                .await?;
+                            info!(
+                                "Voided Firefly journal({}) for Up Bank transaction({})",
+                                journal.transaction_journal_id, up_bank_id
+                            );
+                        }
+                    }
+                }
+                DeletionPolicy::Ignore => {}
+            }
+        }
+
+        self.transaction_tracker.mark_deleted(up_bank_id)?;
+        Ok(true)
+    }
+
+    fn account_for(&self, transaction: &up_bank::transactions::Transaction) -> Option<&AccountMap> {
+        let account = transaction.relationships.account.data.as_ref()?;
+        self.account_map
+            .iter()
+            .find(|f| f.up_account_id == account.id)
+    }
+
+    /// True when the transaction belongs to a mapped account and was made on or after the import
+    /// start date of that account.
+    fn is_account_mapped(&self, transaction: &up_bank::transactions::Transaction) -> bool {
+        match self.account_for(transaction) {
+            Some(account) => account
+                .import_start_date
+                .map(|f| transaction.attributes.created_at.date_naive() >= f)
+                .unwrap_or(true),
+            None => false,
+        }
+    }
+
+    async fn process_transaction(
+        &mut self,
+        transaction: &up_bank::transactions::Transaction,
+    ) -> Result<ProcessOutcome> {
+        let outcome = match self.transaction_tracker.find_transaction(transaction)? {
+            transaction_tracker::Status::NotFound
+                if transaction.attributes.status == TransactionStatus::Held
+                    && self.config.held_transactions == HeldTransactionPolicy::Skip =>
+            {
+                debug!(
+                    "Transaction({}) is held and held transactions are skipped",
+                    transaction.id
+                );
+                ProcessOutcome::Skipped
+            }
+            transaction_tracker::Status::NotFound => {
+                let outcome = self.new_transaction(transaction, IMPORT_TAG).await?;
+                if let ProcessOutcome::AlreadyImported = outcome {
+                    // Since we do not already have a hash we wont know if it needs to be updated.
+                    self.transaction_tracker.add_transaction(
+                        transaction,
+                        TransactionType::Duplicate,
+                        None,
+                    )?;
+                }
+                outcome
+            }
+            transaction_tracker::Status::FoundExact => {
+                debug!(
+                    "Transaction({}) found in TransactionMap with no update required, skipping",
+                    transaction.id
+                );
+                self.transaction_tracker.add_transaction(
+                    transaction,
+                    TransactionType::Duplicate,
+                    None,
+                )?;
+                ProcessOutcome::AlreadyImported
+            }
+            transaction_tracker::Status::FoundNotExact if self.was_dropped(transaction)? => {
+                // The rules may no longer drop it now that it has changed.
+                self.new_transaction(transaction, IMPORT_TAG).await?
+            }
+            transaction_tracker::Status::FoundNotExact => {
+                match self.update_transaction(transaction).await {
+                    Ok(()) => ProcessOutcome::Updated,
+                    Err(e) if is_fatal(&e) => return Err(e),
+                    Err(e) => {
+                        error!(
+                            "Transaction({}) failed to update, it will be retried on the next run, error: {:?}",
+                            transaction.id, e
+                        );
+                        self.transaction_tracker
+                            .record_error(&transaction.id, &e.to_string())?;
+                        ProcessOutcome::UpdateFailed
+                    }
+                }
+            }
+        };
+        Ok(outcome)
+    }
+
+    fn was_dropped(&self, transaction: &up_bank::transactions::Transaction) -> Result<bool> {
+        Ok(self
+            .transaction_tracker
+            .get_tracked_transaction(&transaction.id)?
+            .map(|f| f.transaction_type == Some(TransactionType::Dropped))
+            .unwrap_or(false))
+    }
+
     pub async fn update_transaction(
         &mut self,
         transaction: &up_bank::transactions::Transaction,
@@ -113,6 +537,7 @@ impl Migrator {
             .into_iter()
             .next()
             .ok_or(eyre!("A transaction should have existed in the array"))?;
+        let group_id = fire_fly_transaction.id;
 
         let mut fire_fly_transaction = fire_fly_transaction
             .attributes
@@ -120,14 +545,25 @@ impl Migrator {
             .into_iter()
             .next()
             .ok_or_else(|| eyre!("A transaction should have existed here"))?;
+        let original_transaction = fire_fly_transaction.clone();
 
         // Grab the latest category
-        fire_fly_transaction.category_name = transaction
-            .relationships
-            .category
-            .data
-            .as_ref()
-            .map(|f| f.id.clone().replace('-', "_"));
+        (
+            fire_fly_transaction.category_id,
+            fire_fly_transaction.category_name,
+        ) = self
+            .category_map
+            .resolve(transaction)
+            .map(|f| f.into_fields())
+            .unwrap_or_default();
+
+        self.apply_parent_category(
+            transaction,
+            &mut fire_fly_transaction.budget_id,
+            &mut fire_fly_transaction.budget_name,
+            &mut fire_fly_transaction.tags,
+        );
+        self.apply_notes(transaction, &mut fire_fly_transaction.notes, false);
 
         // Collect all the tags in up bank
         let transaction_tags: Vec<String> = transaction
@@ -141,79 +577,903 @@ impl Migrator {
         // Merge tags into one
         fire_fly_transaction.tags.extend(transaction_tags);
 
+        self.rules.evaluate(transaction).apply_details(
+            &mut fire_fly_transaction.category_id,
+            &mut fire_fly_transaction.category_name,
+            &mut fire_fly_transaction.budget_id,
+            &mut fire_fly_transaction.budget_name,
+            &mut fire_fly_transaction.tags,
+            &mut fire_fly_transaction.notes,
+        );
+
         // Remove duplicates
         dedup(&mut fire_fly_transaction.tags);
 
-        self.fire_fly_api
-            .update_transaction(fire_fly_transaction)
-            .await?;
-        self.transaction_tracker.update_transaction(transaction)?;
+        // Holds can settle for a different amount, eg fuel and hotels.
+        if transaction.attributes.status == TransactionStatus::Settled
+            && self.config.held_transactions != HeldTransactionPolicy::Skip
+        {
+            transaction_map::apply_settlement(transaction, &mut fire_fly_transaction);
+            transaction_map::apply_settled_date(
+                transaction,
+                self.config.settled_date,
+                &mut fire_fly_transaction.book_date,
+                &mut fire_fly_transaction.process_date,
+            );
+            if self.config.cashback_deposits
+                && transaction.attributes.cashback.is_some()
+                && fire_fly_transaction.transaction_type == "withdrawal"
+            {
+                fire_fly_transaction.amount = splits::amount_before_cashback(transaction);
+            }
+            fire_fly_transaction.tags.retain(|f| f != PENDING_TAG);
+        }
+
+        let journal_id = fire_fly_transaction.transaction_journal_id.clone();
+        let group_title = fire_fly_transaction.description.clone();
+        let is_withdrawal = fire_fly_transaction.transaction_type == "withdrawal";
+        if self.dry_run {
+            self.plan.add_update(
+                &transaction.id,
+                &original_transaction,
+                &fire_fly_transaction,
+            );
+        } else {
+            self.fire_fly_api
+                .update_transaction(&group_id, fire_fly_transaction)
+                .await?;
+        }
+
+        if is_withdrawal {
+            if let transaction_map::TransferType::Transaction(purchase) =
+                transaction_map::convert_up_bank_transaction_to_fire_fly(
+                    transaction,
+                    &self.account_map,
+                    &self.category_map,
+                )?
+            {
+                let linked_splits = self.linked_splits(transaction, &purchase);
+                self.sync_linked_groups(&transaction.id, &group_title, &linked_splits)
+                    .await?;
+            }
+        }
+        // Only reached once Firefly accepted the update, a failed update keeps the old hash so it is
+        // retried on the next run.
+        self.transaction_tracker
+            .update_transaction(transaction, &journal_id)?;
         Ok(())
     }
 
-    pub async fn new_transaction(
+    /// Imports a transaction that is not tracked yet, returns AlreadyImported when it was found in
+    /// Firefly instead.
+    async fn new_transaction(
         &mut self,
         transaction: &up_bank::transactions::Transaction,
         tag: &str,
-    ) -> Result<bool> {
-        let was_found =
-            transaction_map::find_up_bank_transaction_in_fire_fly(transaction, &self.fire_fly_api)
-                .await?;
-        if !was_found {
-            debug!("Importing up bank transaction: {}", transaction.id);
-            match self
-                .migrate_transaction(transaction, &Some(tag.to_string()))
-                .await
-            {
-                Ok(resp) => {
-                    self.transaction_tracker.add_transaction(transaction, resp);
-                }
-                Err(e) => error!(
-                    "Transaction({}) failed to import, error: {:?}",
-                    transaction.id, e
-                ),
-            }
-        } else {
+    ) -> Result<ProcessOutcome> {
+        // The purchase was created by an import that failed part way through, so the rest of it
+        // still has to be created rather than it being treated as imported by someone else.
+        let resuming = self
+            .transaction_tracker
+            .get_groups(&transaction.id)?
+            .iter()
+            .any(|f| f.split == SplitKind::Purchase);
+        let was_found = !resuming
+            && transaction_map::find_up_bank_transaction_in_fire_fly(
+                transaction,
+                &self.fire_fly_api,
+            )
+            .await?;
+        if resuming {
+            info!(
+                "Transaction({}) was only partly imported, creating the rest of it",
+                transaction.id
+            );
+        }
+        if was_found {
             debug!(
                 "Transaction {} was already found in fire fly",
                 transaction.id
-            )
+            );
+            return Ok(ProcessOutcome::AlreadyImported);
         }
 
-        Ok(!was_found)
+        debug!("Importing up bank transaction: {}", transaction.id);
+        let outcome = match self
+            .migrate_transaction(transaction, &Some(tag.to_string()))
+            .await
+        {
+            Ok((transaction_type, journal_id)) => {
+                self.transaction_tracker.add_transaction(
+                    transaction,
+                    transaction_type,
+                    journal_id.as_deref(),
+                )?;
+                if transaction_type == TransactionType::Dropped {
+                    ProcessOutcome::Dropped
+                } else {
+                    ProcessOutcome::New
+                }
+            }
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(e) => {
+                error!(
+                    "Transaction({}) failed to import, error: {:?}",
+                    transaction.id, e
+                );
+                self.transaction_tracker
+                    .record_error(&transaction.id, &e.to_string())?;
+                ProcessOutcome::Failed
+            }
+        };
+        Ok(outcome)
     }
 
     pub async fn migrate_transaction(
-        &self,
+        &mut self,
         up_bank_transaction: &up_bank::transactions::Transaction,
         import_tag: &Option<String>,
-    ) -> Result<TransactionType> {
+    ) -> Result<(TransactionType, Option<String>)> {
+        if let Some(round_up_account) = &self.round_up_account {
+            if splits::is_round_up_deposit(up_bank_transaction, &round_up_account.up_account_id) {
+                return Ok((TransactionType::TransferDuplicate, None));
+            }
+        }
+
         match transaction_map::convert_up_bank_transaction_to_fire_fly(
             up_bank_transaction,
             &self.account_map,
+            &self.category_map,
         )? {
             transaction_map::TransferType::Transaction(mut fire_fly_payload) => {
-                match import_tag {
-                    Some(tag) => {
-                        fire_fly_payload.tags.push(tag.to_string());
+                if let Some(tag) = import_tag {
+                    fire_fly_payload.tags.push(tag.to_string());
+                }
+                if let Some(account) = self.account_for(up_bank_transaction) {
+                    fire_fly_payload.tags.extend(account.tags.clone());
+                }
+                self.apply_parent_category(
+                    up_bank_transaction,
+                    &mut fire_fly_payload.budget_id,
+                    &mut fire_fly_payload.budget_name,
+                    &mut fire_fly_payload.tags,
+                );
+                self.apply_notes(up_bank_transaction, &mut fire_fly_payload.notes, true);
+                if let Some(code) = fire_fly_payload.currency_code.clone() {
+                    fire_fly_payload.currency_id = Some(self.resolve_currency(&code).await?);
+                }
+                if let Some(code) = fire_fly_payload.foreign_currency_code.clone() {
+                    fire_fly_payload.foreign_currency_id =
+                        Some(self.resolve_currency(&code).await?);
+                }
+                transaction_map::apply_settled_date(
+                    up_bank_transaction,
+                    self.config.settled_date,
+                    &mut fire_fly_payload.book_date,
+                    &mut fire_fly_payload.process_date,
+                );
+
+                // Only names taken from the description are replaced, not linked accounts.
+                if let Some(payee) = self.payee_normaliser.normalise(up_bank_transaction) {
+                    let description = Some(up_bank_transaction.attributes.description.clone());
+                    if fire_fly_payload.destination_name == description {
+                        fire_fly_payload.destination_name = Some(payee);
+                    } else if fire_fly_payload.source_name == description {
+                        fire_fly_payload.source_name = Some(payee);
+                    }
+                }
+
+                let evaluation = self.rules.evaluate(up_bank_transaction);
+                if evaluation.actions.drop {
+                    info!(
+                        "Transaction({}) dropped by rules: {}",
+                        up_bank_transaction.id,
+                        evaluation.matched_rules.join(", ")
+                    );
+                    if self.dry_run {
+                        self.plan.add_drop(
+                            &up_bank_transaction.id,
+                            &fire_fly_payload,
+                            &evaluation.matched_rules,
+                        );
                     }
-                    None => {}
-                };
-                self.fire_fly_api
-                    .submit_new_transaction(&fire_fly_payload)
+                    return Ok((TransactionType::Dropped, None));
+                }
+                evaluation.apply(&mut fire_fly_payload);
+                if up_bank_transaction.attributes.status == TransactionStatus::Held
+                    && self.config.held_transactions == HeldTransactionPolicy::Pending
+                {
+                    fire_fly_payload.tags.push(PENDING_TAG.to_string());
+                    fire_fly_payload.reconciled = false;
+                }
+
+                let transaction_type =
+                    TransactionType::string_to_enum(&fire_fly_payload.transaction_type);
+                let linked_splits = self.linked_splits(up_bank_transaction, &fire_fly_payload);
+                if linked_splits.iter().any(|f| f.kind == SplitKind::Cashback) {
+                    fire_fly_payload.amount = splits::amount_before_cashback(up_bank_transaction);
+                }
+
+                let journal_id = self
+                    .submit_splits(&up_bank_transaction.id, &fire_fly_payload, &linked_splits)
                     .await?;
-                Ok(TransactionType::string_to_enum(
-                    &fire_fly_payload.transaction_type,
-                ))
+                Ok((transaction_type, journal_id))
             }
             transaction_map::TransferType::TransactionDuplicate => {
-                Ok(TransactionType::TransferDuplicate)
+                Ok((TransactionType::TransferDuplicate, None))
+            }
+        }
+    }
+
+    /// Records the parent category of a transaction as a budget or a tag depending on the settings.
+    fn apply_parent_category(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+        budget_id: &mut Option<String>,
+        budget_name: &mut Option<String>,
+        tags: &mut Vec<String>,
+    ) {
+        let target = self.config.parent_categories;
+        if target == ParentCategoryTarget::None {
+            return;
+        }
+        let Some(parent) = self.category_map.resolve_parent(up_bank_transaction) else {
+            return;
+        };
+
+        match target {
+            ParentCategoryTarget::Budget => {
+                (*budget_id, *budget_name) = FireFlyReference::parse(&parent).into_fields();
+            }
+            ParentCategoryTarget::Tag => {
+                tags.push(parent);
+                dedup(tags);
+            }
+            ParentCategoryTarget::None => {}
+        }
+    }
+
+    /// Renders the notes template into the notes of a new transaction when one is configured. The
+    /// notes of an existing transaction are kept as they are in Firefly, which may have been
+    /// edited since, apart from the exchange rate which changes when a hold settles.
+    fn apply_notes(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+        notes: &mut Option<String>,
+        is_new: bool,
+    ) {
+        let mut lines: Vec<String> = notes
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .filter(|f| !f.starts_with(transaction_map::EXCHANGE_RATE_PREFIX))
+            .map(|f| f.to_string())
+            .collect();
+        if let (true, Some(template)) = (is_new, &self.config.notes_template) {
+            if let Some(rendered) = transaction_map::render_notes(template, up_bank_transaction) {
+                lines.extend(rendered.lines().map(|f| f.to_string()));
+            }
+        }
+        if let Some(exchange_rate) = transaction_map::exchange_rate_note(up_bank_transaction) {
+            lines.push(exchange_rate);
+        }
+
+        *notes = if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        };
+    }
+
+    /// Looks up the Firefly id of a currency by its code, enabling the currency in Firefly when it
+    /// exists but is disabled. Currencies are only fetched from Firefly once per migrator.
+    async fn resolve_currency(&mut self, code: &str) -> Result<String> {
+        if self.currencies.is_none() {
+            let currencies = self
+                .fire_fly_api
+                .get_all_currencies()
+                .await?
+                .into_iter()
+                .map(|f| (f.attributes.code.clone(), f))
+                .collect();
+            self.currencies = Some(currencies);
+        }
+        let currencies = self
+            .currencies
+            .as_mut()
+            .ok_or_else(|| eyre!("Firefly currencies should have been loaded"))?;
+
+        let currency = currencies
+            .get(code)
+            .cloned()
+            .ok_or_else(|| eyre!("Currency ({}) does not exist in Firefly", code))?;
+        if !currency.attributes.enabled {
+            if self.dry_run {
+                info!(
+                    "Currency({}) is disabled in Firefly and would be enabled",
+                    code
+                );
+            } else {
+                let enabled_currency = self.fire_fly_api.enable_currency(code).await?;
+                info!("Enabled currency({}) in Firefly", code);
+                currencies.insert(code.to_string(), enabled_currency);
+            }
+        }
+        Ok(currency.id)
+    }
+
+    /// Builds the transactions created alongside a purchase, eg its round up or cashback.
+    fn linked_splits(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+        purchase: &fire_fly::transaction::TransactionPayload,
+    ) -> Vec<Split> {
+        let mut linked_splits = vec![];
+        if purchase.transaction_type != "withdrawal" {
+            return linked_splits;
+        }
+        if let Some(round_up_account) = &self.round_up_account {
+            linked_splits.extend(splits::round_up_splits(
+                up_bank_transaction,
+                purchase,
+                &round_up_account.fire_fly_account_id,
+                self.config.round_up_boost,
+                &self.config.boost_revenue_account,
+            ));
+        }
+        if self.config.cashback_deposits {
+            linked_splits.extend(splits::cashback_split(
+                up_bank_transaction,
+                purchase,
+                &self.config.cashback_revenue_account,
+            ));
+        }
+        linked_splits
+    }
+
+    /// True when the settings still create this kind of split, groups of a kind that was turned
+    /// off are left alone rather than deleted.
+    fn creates_split(&self, split: SplitKind) -> bool {
+        match split {
+            SplitKind::Purchase => true,
+            SplitKind::RoundUp | SplitKind::Boost => self.round_up_account.is_some(),
+            SplitKind::Cashback => self.config.cashback_deposits,
+        }
+    }
+
+    /// Submits a purchase and the transactions created alongside it, each in a group of its own
+    /// sharing the purchase description as group title, and returns the journal id of the
+    /// purchase. Every group is recorded as soon as it is created, groups recorded by an earlier
+    /// attempt that failed part way through are not created again.
+    async fn submit_splits(
+        &mut self,
+        up_bank_id: &str,
+        purchase: &fire_fly::transaction::TransactionPayload,
+        linked_splits: &[Split],
+    ) -> Result<Option<String>> {
+        let existing_groups = self.transaction_tracker.get_groups(up_bank_id)?;
+        let group_title = if linked_splits.is_empty() {
+            String::new()
+        } else {
+            purchase.description.clone()
+        };
+        let purchase = Split {
+            kind: SplitKind::Purchase,
+            payload: purchase.clone(),
+        };
+
+        let mut journal_id = None;
+        for split in std::iter::once(&purchase).chain(linked_splits) {
+            let split_journal_id = match existing_groups.iter().find(|f| f.split == split.kind) {
+                Some(group) => {
+                    debug!(
+                        "Transaction({}) {} was already created as Firefly transaction group({})",
+                        up_bank_id,
+                        split.kind.as_str(),
+                        group.group_id
+                    );
+                    Some(group.journal_id.clone())
+                }
+                None => self.create_group(up_bank_id, &group_title, split).await?,
+            };
+            if split.kind == SplitKind::Purchase {
+                journal_id = split_journal_id;
+            }
+        }
+        Ok(journal_id)
+    }
+
+    /// Creates a group for a single split and records it, returns its journal id or None during
+    /// a dry run.
+    async fn create_group(
+        &mut self,
+        up_bank_id: &str,
+        group_title: &str,
+        split: &Split,
+    ) -> Result<Option<String>> {
+        if self.dry_run {
+            self.plan
+                .add_create(&split.kind.external_id(up_bank_id), &split.payload);
+            return Ok(None);
+        }
+
+        let group = self
+            .fire_fly_api
+            .submit_transaction_group(group_title, std::slice::from_ref(&split.payload))
+            .await?;
+        let journal_id = group.attributes.transactions[0]
+            .transaction_journal_id
+            .clone();
+        self.transaction_tracker
+            .record_group(up_bank_id, split.kind, &group.id, &journal_id)?;
+        Ok(Some(journal_id))
+    }
+
+    /// Brings the groups created alongside a purchase in line with the splits it has now, eg a
+    /// round up that changed when the purchase settled. Missing groups are created and groups that
+    /// are no longer needed deleted.
+    async fn sync_linked_groups(
+        &mut self,
+        up_bank_id: &str,
+        group_title: &str,
+        linked_splits: &[Split],
+    ) -> Result<()> {
+        let recorded_groups: Vec<FireFlyGroup> = self
+            .transaction_tracker
+            .get_groups(up_bank_id)?
+            .into_iter()
+            .filter(|f| f.split != SplitKind::Purchase)
+            .collect();
+
+        for split in linked_splits {
+            match recorded_groups.iter().find(|f| f.split == split.kind) {
+                Some(group) => self.update_group(up_bank_id, group, &split.payload).await?,
+                None => {
+                    self.create_group(up_bank_id, group_title, split).await?;
+                }
+            }
+        }
+
+        for group in &recorded_groups {
+            if self.creates_split(group.split)
+                && !linked_splits.iter().any(|f| f.kind == group.split)
+            {
+                self.delete_group(up_bank_id, group).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches a recorded group from Firefly, None when it has been deleted there.
+    async fn get_group(
+        &self,
+        up_bank_id: &str,
+        group: &FireFlyGroup,
+    ) -> Result<Option<fire_fly::transaction::TransactionData>> {
+        match self
+            .fire_fly_api
+            .get_transaction_group(&group.group_id)
+            .await
+        {
+            Ok(fire_fly_transaction) => Ok(Some(fire_fly_transaction)),
+            Err(fire_fly::error::Error::NotFound(_)) => {
+                warn!(
+                    "Firefly transaction group({}) for the {} of Up Bank transaction({}) no longer exists",
+                    group.group_id,
+                    group.split.as_str(),
+                    up_bank_id
+                );
+                Ok(None)
             }
+            Err(e) => Err(e.into()),
         }
     }
+
+    /// Updates the amount, date and description of a recorded group when they changed.
+    async fn update_group(
+        &mut self,
+        up_bank_id: &str,
+        group: &FireFlyGroup,
+        payload: &fire_fly::transaction::TransactionPayload,
+    ) -> Result<()> {
+        let Some(fire_fly_transaction) = self.get_group(up_bank_id, group).await? else {
+            return Ok(());
+        };
+        let Some(journal) = fire_fly_transaction
+            .attributes
+            .transactions
+            .into_iter()
+            .find(|f| f.transaction_journal_id == group.journal_id)
+        else {
+            return Err(eyre!(
+                "Firefly transaction group({}) no longer contains journal({})",
+                group.group_id,
+                group.journal_id
+            ));
+        };
+
+        let mut updated_journal = journal.clone();
+        updated_journal.amount = payload.amount;
+        updated_journal.date = payload.date;
+        updated_journal.description = payload.description.clone();
+        if updated_journal.amount == journal.amount
+            && updated_journal.date == journal.date
+            && updated_journal.description == journal.description
+        {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            self.plan.add_update(up_bank_id, &journal, &updated_journal);
+        } else {
+            self.fire_fly_api
+                .update_transaction(&group.group_id, updated_journal)
+                .await?;
+            info!(
+                "Updated the {} of Up Bank transaction({}) in Firefly transaction group({})",
+                group.split.as_str(),
+                up_bank_id,
+                group.group_id
+            );
+        }
+        Ok(())
+    }
+
+    async fn delete_group(&mut self, up_bank_id: &str, group: &FireFlyGroup) -> Result<()> {
+        if self.dry_run {
+            self.plan.add_delete(up_bank_id, &group.group_id);
+        } else {
+            match self
+                .fire_fly_api
+                .delete_transaction_group(&group.group_id)
+                .await
+            {
+                Ok(()) | Err(fire_fly::error::Error::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+            info!(
+                "Deleted the {} of Up Bank transaction({}), Firefly transaction group({})",
+                group.split.as_str(),
+                up_bank_id,
+                group.group_id
+            );
+        }
+        self.transaction_tracker
+            .remove_group(up_bank_id, group.split)
+    }
 }
 
 fn dedup(v: &mut Vec<String>) {
     let mut uniques = HashSet::new();
     v.retain(|e| uniques.insert(e.clone()));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fire_fly::fake::FakeFireFly;
+    use crate::up_bank::transactions::fixtures::transaction;
+
+    /// A migrator importing the `acc1` Up Bank account into Firefly account `1`, `settings` is
+    /// extra settings.yaml content.
+    fn migrator(fire_fly: &FakeFireFly, settings: &str) -> Migrator {
+        let config: Config = serde_yaml::from_str(&format!(
+            "upbank_pan: token\nff_pan: token\nff_url: {}\n{settings}",
+            fire_fly.url
+        ))
+        .unwrap();
+        Migrator {
+            up_bank_api: up_bank::UpBank::create(
+                "token".to_string(),
+                "http://127.0.0.1:9".to_string(),
+                &config.http,
+            )
+            .unwrap(),
+            fire_fly_api: fire_fly.client(),
+            account_map: vec![AccountMap::create("acc1".to_string(), "1".to_string())],
+            category_map: CategoryMap::create(
+                &config.category_mapping,
+                &config.parent_category_mapping,
+                &[],
+            ),
+            currencies: None,
+            rules: Rules::default(),
+            payee_normaliser: PayeeNormaliser::create(&[], false).unwrap(),
+            transaction_tracker: TransactionHashData::in_memory(),
+            round_up_account: None,
+            config,
+            dry_run: false,
+            plan: Plan::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_plans_changes_without_writing() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "deleted_transactions: void");
+        for (id, description, amount) in [("tx2", "Cafe", "-4.50"), ("tx3", "Bakery", "-8.00")] {
+            let outcome = migrator
+                .process_transaction(&transaction(id, description, amount))
+                .await
+                .unwrap();
+            assert!(matches!(outcome, ProcessOutcome::New));
+        }
+        let writes = fire_fly.writes();
+        let groups = serde_json::to_value(fire_fly.groups()).unwrap();
+
+        migrator.dry_run = true;
+        let created = migrator
+            .process_transaction(&transaction("tx1", "Bar", "-12.00"))
+            .await
+            .unwrap();
+        let updated = migrator
+            .process_transaction(&transaction("tx2", "Cafe", "-5.00"))
+            .await
+            .unwrap();
+        let voided = migrator.remove_deleted_transaction("tx3").await.unwrap();
+
+        assert!(matches!(created, ProcessOutcome::New));
+        assert!(matches!(updated, ProcessOutcome::Updated));
+        assert!(voided);
+        assert_eq!(fire_fly.writes(), writes);
+        assert_eq!(serde_json::to_value(fire_fly.groups()).unwrap(), groups);
+
+        let changes = migrator.plan.changes();
+        assert_eq!(changes.len(), 3);
+        assert!(matches!(
+            &changes[0],
+            plan::PlannedChange::Create { up_bank_id, payload }
+                if up_bank_id == "tx1" && payload.amount.to_string() == "12.00"
+        ));
+        assert!(matches!(
+            &changes[1],
+            plan::PlannedChange::Update { up_bank_id, changes, .. }
+                if up_bank_id == "tx2"
+                    && changes.iter().any(|f| f.field == "amount" && f.after == "\"5.00\"")
+        ));
+        assert!(matches!(
+            &changes[2],
+            plan::PlannedChange::Update { up_bank_id, changes, .. }
+                if up_bank_id == "tx3"
+                    && changes.iter().any(|f| f.field == "tags" && f.after.contains(VOIDED_TAG))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleted_transactions_are_removed_from_fire_fly() {
+        for (policy, removed, voided) in [("delete", true, false), ("void", false, true)] {
+            let fire_fly = FakeFireFly::start();
+            let mut migrator = migrator(&fire_fly, &format!("deleted_transactions: {policy}"));
+            let kept = transaction("tx1", "Cafe", "-4.50");
+            let deleted = transaction("tx2", "Bakery", "-8.00");
+            for f in [&kept, &deleted] {
+                migrator.process_transaction(f).await.unwrap();
+            }
+
+            // Up Bank only returned the first transaction this time.
+            let fetched_ids = HashSet::from([kept.id.clone()]);
+            let count = migrator
+                .remove_deleted_transactions(None, None, &fetched_ids)
+                .await
+                .unwrap();
+
+            assert_eq!(count, 1, "{policy}");
+            let journals = fire_fly_journals(&fire_fly);
+            assert_eq!(journals.len(), if removed { 1 } else { 2 }, "{policy}");
+            for journal in journals {
+                let is_voided = journal.tags.iter().any(|f| f == VOIDED_TAG);
+                let expected = voided && journal.external_id.as_deref() == Some("tx2");
+                assert_eq!(is_voided, expected, "{policy}");
+            }
+            assert!(migrator
+                .transaction_tracker
+                .get_tracked_transactions()
+                .unwrap()
+                .iter()
+                .all(|f| f.up_bank_id == "tx1"));
+
+            // Already removed, so the next import leaves Firefly alone.
+            let writes = fire_fly.writes();
+            assert_eq!(
+                migrator
+                    .remove_deleted_transactions(None, None, &fetched_ids)
+                    .await
+                    .unwrap(),
+                0
+            );
+            assert_eq!(fire_fly.writes(), writes);
+        }
+    }
+
+    #[tokio::test]
+    async fn deletions_are_ignored_by_default() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "");
+        migrator
+            .process_transaction(&transaction("tx1", "Cafe", "-4.50"))
+            .await
+            .unwrap();
+        let writes = fire_fly.writes();
+
+        let count = migrator
+            .remove_deleted_transactions(None, None, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(!migrator.remove_deleted_transaction("tx1").await.unwrap());
+
+        assert_eq!(count, 0);
+        assert_eq!(fire_fly.writes(), writes);
+    }
+
+    fn held(id: &str, description: &str, amount: &str) -> up_bank::transactions::Transaction {
+        let mut hold = transaction(id, description, amount);
+        hold.attributes.status = TransactionStatus::Held;
+        hold.attributes.settled_at = None;
+        hold
+    }
+
+    /// The hold once it has settled for a different amount.
+    fn settle(
+        hold: &up_bank::transactions::Transaction,
+        amount: &str,
+    ) -> up_bank::transactions::Transaction {
+        let mut settled = transaction(&hold.id, &hold.attributes.description, amount);
+        settled.attributes.hold_info = Some(up_bank::transactions::HoldInfoObject {
+            amount: hold.attributes.amount.clone(),
+            foreign_amount: None,
+        });
+        settled
+    }
+
+    fn fire_fly_journals(fire_fly: &FakeFireFly) -> Vec<fire_fly::transaction::Transaction> {
+        fire_fly
+            .groups()
+            .into_iter()
+            .flat_map(|f| f.attributes.transactions)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn held_transactions_are_skipped_until_they_settle() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "held_transactions: skip");
+        let hold = held("tx1", "Fuel", "-100.00");
+
+        let outcome = migrator.process_transaction(&hold).await.unwrap();
+        assert!(matches!(outcome, ProcessOutcome::Skipped));
+        assert!(fire_fly.writes().is_empty());
+
+        let outcome = migrator
+            .process_transaction(&settle(&hold, "-62.35"))
+            .await
+            .unwrap();
+        assert!(matches!(outcome, ProcessOutcome::New));
+        let journals = fire_fly_journals(&fire_fly);
+        assert_eq!(journals.len(), 1);
+        assert_eq!(journals[0].amount.to_string(), "62.35");
+        assert!(!journals[0].tags.iter().any(|f| f == PENDING_TAG));
+    }
+
+    #[tokio::test]
+    async fn held_transactions_are_imported_and_updated_once_they_settle() {
+        for (policy, tagged) in [("import", false), ("pending", true)] {
+            let fire_fly = FakeFireFly::start();
+            let mut migrator = migrator(&fire_fly, &format!("held_transactions: {policy}"));
+            let hold = held("tx1", "Fuel", "-100.00");
+
+            let outcome = migrator.process_transaction(&hold).await.unwrap();
+            assert!(matches!(outcome, ProcessOutcome::New), "{policy}");
+            let journals = fire_fly_journals(&fire_fly);
+            assert_eq!(journals[0].amount.to_string(), "100.00", "{policy}");
+            assert_eq!(
+                journals[0].tags.iter().any(|f| f == PENDING_TAG),
+                tagged,
+                "{policy}"
+            );
+
+            let outcome = migrator
+                .process_transaction(&settle(&hold, "-62.35"))
+                .await
+                .unwrap();
+            assert!(matches!(outcome, ProcessOutcome::Updated), "{policy}");
+            let journals = fire_fly_journals(&fire_fly);
+            assert_eq!(journals.len(), 1, "{policy}");
+            assert_eq!(journals[0].amount.to_string(), "62.35", "{policy}");
+            assert!(
+                !journals[0].tags.iter().any(|f| f == PENDING_TAG),
+                "{policy}"
+            );
+        }
+    }
+
+    fn overseas(id: &str, currency_code: &str) -> up_bank::transactions::Transaction {
+        let mut purchase = transaction(id, "Amazon", "-15.23");
+        purchase.attributes.foreign_amount = Some(up_bank::general::Money {
+            currency_code: currency_code.to_string(),
+            value: "-10.00".parse().unwrap(),
+            value_in_base_units: -1000,
+        });
+        purchase
+    }
+
+    #[tokio::test]
+    async fn disabled_foreign_currencies_are_enabled() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "");
+
+        let outcome = migrator
+            .process_transaction(&overseas("tx1", "USD"))
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, ProcessOutcome::New));
+        assert_eq!(
+            fire_fly.writes(),
+            vec![
+                "POST /api/v1/currencies/USD/enable",
+                "POST /api/v1/transactions"
+            ]
+        );
+        let journals = fire_fly_journals(&fire_fly);
+        assert_eq!(journals[0].currency_id.as_deref(), Some("1"));
+        assert_eq!(journals[0].foreign_currency_id.as_deref(), Some("2"));
+        assert_eq!(journals[0].foreign_currency_code.as_deref(), Some("USD"));
+        assert_eq!(
+            journals[0].notes.as_deref(),
+            Some("Exchange rate: 1 USD = 1.523 AUD")
+        );
+
+        // Currencies are only fetched and enabled once.
+        migrator
+            .process_transaction(&overseas("tx2", "USD"))
+            .await
+            .unwrap();
+        assert_eq!(fire_fly.writes().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn unknown_foreign_currencies_fail_the_import() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "");
+
+        let outcome = migrator
+            .process_transaction(&overseas("tx1", "JPY"))
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, ProcessOutcome::Failed));
+        assert!(fire_fly.writes().is_empty());
+        let tracked = migrator
+            .transaction_tracker
+            .get_tracked_transaction("tx1")
+            .unwrap();
+        assert!(tracked.is_none());
+    }
+
+    #[tokio::test]
+    async fn transactions_before_the_import_start_date_are_not_deleted() {
+        let fire_fly = FakeFireFly::start();
+        let mut migrator = migrator(&fire_fly, "deleted_transactions: delete");
+        migrator
+            .process_transaction(&transaction("tx1", "Cafe", "-4.50"))
+            .await
+            .unwrap();
+        let writes = fire_fly.writes();
+
+        // The start date was moved past the transaction, so Up Bank results no longer include it.
+        migrator.account_map[0].import_start_date = chrono::NaiveDate::from_ymd_opt(2023, 3, 1);
+        let count = migrator
+            .remove_deleted_transactions(None, None, &HashSet::new())
+            .await
+            .unwrap();
+
+        assert_eq!(count, 0);
+        assert_eq!(fire_fly.writes(), writes);
+        assert_eq!(fire_fly.groups().len(), 1);
+        assert!(migrator
+            .transaction_tracker
+            .get_tracked_transaction("tx1")
+            .unwrap()
+            .is_some());
+    }
+}
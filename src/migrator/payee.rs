@@ -0,0 +1,279 @@
+use color_eyre::eyre::{eyre, Result};
+use regex::Regex;
+
+use crate::{config::PayeeAlias, up_bank};
+
+// Prefixes card processors add in front of the merchant name, compared ignoring case.
+const PROCESSOR_PREFIXES: &[&str] = &[
+    "SQ *", "SQ*", "SQUARE *", "SMP*", "ZLR*", "TST*", "LS ", "SP *", "SP*", "IZ *", "IZ*",
+    "SUMUP *", "PAYPAL *", "PP*",
+];
+
+// Most words after a store number that are still taken as its location, eg `BONDI JUNCTION`.
+const MAX_LOCALITY_WORDS: usize = 3;
+
+// State and country codes merchants put at the end of their name.
+const LOCATION_SUFFIXES: &[&str] = &[
+    "NSW",
+    "VIC",
+    "QLD",
+    "SA",
+    "WA",
+    "TAS",
+    "NT",
+    "ACT",
+    "AU",
+    "AUS",
+    "AUSTRALIA",
+];
+
+/// Maps the many variants of a merchant name Up Bank reports onto a single Firefly expense or
+/// revenue account name.
+pub struct PayeeNormaliser {
+    aliases: Vec<(String, Vec<Regex>)>,
+    clean_names: bool,
+}
+
+impl PayeeNormaliser {
+    pub fn create(aliases: &[PayeeAlias], clean_names: bool) -> Result<Self> {
+        let aliases = aliases
+            .iter()
+            .map(|alias| {
+                let patterns = alias
+                    .patterns
+                    .iter()
+                    .map(|pattern| {
+                        Regex::new(pattern).map_err(|e| {
+                            eyre!(
+                                "Payee alias ({}) has an invalid pattern ({}), error: {}",
+                                alias.name,
+                                pattern,
+                                e
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<Regex>>>()?;
+                Ok((alias.name.clone(), patterns))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            aliases,
+            clean_names,
+        })
+    }
+
+    /// Returns the canonical payee name of a transaction. Aliases are checked first, in the order
+    /// they are configured, against both the description and raw text, then the description is
+    /// cleaned up when enabled. Returns None when the description should be used as is.
+    pub fn normalise(
+        &self,
+        up_bank_transaction: &up_bank::transactions::Transaction,
+    ) -> Option<String> {
+        let attributes = &up_bank_transaction.attributes;
+        for (name, patterns) in &self.aliases {
+            let is_match = patterns.iter().any(|pattern| {
+                pattern.is_match(&attributes.description)
+                    || attributes
+                        .raw_text
+                        .as_ref()
+                        .map(|f| pattern.is_match(f))
+                        .unwrap_or(false)
+            });
+            if is_match {
+                return Some(name.clone());
+            }
+        }
+
+        if self.clean_names {
+            return Some(clean_payee_name(&attributes.description));
+        }
+        None
+    }
+}
+
+/// Removes card processor prefixes, store numbers and locations from a merchant name, eg
+/// `SQ *CAFE 123 SYDNEY` becomes `Cafe`. A store number is three or more digits or written as
+/// `#12`, anything after one is taken as the location of the store as long as it is a few words
+/// without numbers. Short numbers are kept as part of the name, so `Shop 7 Cafe` is left alone.
+pub fn clean_payee_name(name: &str) -> String {
+    let mut cleaned = name.trim();
+    while let Some(prefix) = PROCESSOR_PREFIXES.iter().find(|prefix| {
+        cleaned.len() > prefix.len()
+            && cleaned.is_char_boundary(prefix.len())
+            && cleaned[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }) {
+        cleaned = cleaned[prefix.len()..].trim_start();
+    }
+
+    let mut words: Vec<&str> = cleaned.split_whitespace().collect();
+    while words.len() > 1 && words.last().map(|f| is_location_suffix(f)).unwrap_or(false) {
+        words.pop();
+    }
+    // The first word is always kept, eg `7 Eleven`.
+    if let Some(position) = (1..words.len()).rev().find(|f| {
+        is_store_number(words[*f])
+            && words.len() - f - 1 <= MAX_LOCALITY_WORDS
+            && words[f + 1..].iter().all(|e| is_locality_word(e))
+    }) {
+        words.truncate(position);
+    }
+
+    let cleaned = words.join(" ");
+    if cleaned.is_empty() {
+        return name.trim().to_string();
+    }
+    if cleaned.chars().any(|f| f.is_lowercase()) {
+        cleaned
+    } else {
+        title_case(&cleaned)
+    }
+}
+
+fn is_location_suffix(word: &str) -> bool {
+    LOCATION_SUFFIXES
+        .iter()
+        .any(|f| word.eq_ignore_ascii_case(f))
+}
+
+fn is_store_number(word: &str) -> bool {
+    let (digits, min_length) = match word.strip_prefix('#') {
+        Some(digits) => (digits, 1),
+        None => (word, 3),
+    };
+    digits.len() >= min_length && digits.chars().all(|f| f.is_ascii_digit())
+}
+
+fn is_locality_word(word: &str) -> bool {
+    word.chars()
+        .all(|f| f.is_alphabetic() || f == '-' || f == '\'')
+}
+
+/// Capitalises the first letter of each word, short words without vowels such as `BP` or `KFC`
+/// are taken to be acronyms and kept as they are.
+fn title_case(value: &str) -> String {
+    value
+        .split(' ')
+        .map(|word| {
+            if is_acronym(word) {
+                return word.to_string();
+            }
+            let mut capitalise = true;
+            word.chars()
+                .map(|f| {
+                    let next = if capitalise {
+                        f.to_ascii_uppercase()
+                    } else {
+                        f.to_ascii_lowercase()
+                    };
+                    capitalise = f == '-';
+                    next
+                })
+                .collect()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn is_acronym(word: &str) -> bool {
+    (2..=3).contains(&word.len())
+        && word.chars().all(|f| f.is_ascii_uppercase())
+        && !word.chars().any(|f| "AEIOUY".contains(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::up_bank::transactions::fixtures::transaction;
+
+    #[test]
+    fn cleans_merchant_names() {
+        for (name, expected) in [
+            ("SQ *CAFE 123 SYDNEY", "Cafe"),
+            ("SQ *CAFE 123 NSW", "Cafe"),
+            ("SQ *CAFE 456 SYDNEY NSW", "Cafe"),
+            ("WOOLWORTHS 1234 SYDNEY", "Woolworths"),
+            (
+                "WOOLWORTHS METRO 2041 BONDI JUNCTION NSW",
+                "Woolworths Metro",
+            ),
+            ("WOOLWORTHS #1234 BONDI", "Woolworths"),
+            ("7-ELEVEN 2245 BRISBANE QLD", "7-Eleven"),
+            ("7 Eleven 1234 Sydney", "7 Eleven"),
+            ("SQ *BLACK STAR PASTRY", "Black Star Pastry"),
+            ("PAYPAL *STEAM GAMES", "Steam Games"),
+            ("SMP*ROSE BAY CELLARS", "Rose Bay Cellars"),
+            ("KMART 1103", "Kmart"),
+            ("COLES 0456 AU", "Coles"),
+            ("BP CONNECT BONDI JUNCTION NSW", "BP Connect Bondi Junction"),
+            ("KFC 325 PARRAMATTA", "KFC"),
+            ("JB HI-FI 012 MELBOURNE VIC", "JB Hi-Fi"),
+            ("Guzman y Gomez", "Guzman y Gomez"),
+        ] {
+            assert_eq!(clean_payee_name(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn variants_of_a_store_share_one_name() {
+        let names: HashSet<String> = [
+            "SQ *CAFE 123 SYDNEY",
+            "SQ *CAFE 124 SYDNEY",
+            "SQ*CAFE 123 NEWTOWN NSW",
+            "CAFE #9",
+        ]
+        .iter()
+        .map(|f| clean_payee_name(f))
+        .collect();
+        assert_eq!(names, HashSet::from(["Cafe".to_string()]));
+    }
+
+    #[test]
+    fn keeps_numbers_that_are_part_of_the_name() {
+        for (name, expected) in [
+            ("Shop 7 Cafe", "Shop 7 Cafe"),
+            ("Level 3 Bar", "Level 3 Bar"),
+            ("Cafe 63 Fitzroy", "Cafe 63 Fitzroy"),
+            ("7 Eleven", "7 Eleven"),
+            ("1234", "1234"),
+            ("#12 CAFE", "#12 Cafe"),
+            ("CAFE 123 LEVEL 2", "Cafe 123 Level 2"),
+            ("THE GROCER ON 5TH AVE", "The Grocer On 5th Ave"),
+        ] {
+            assert_eq!(clean_payee_name(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn aliases_take_precedence_over_cleaning() {
+        let normaliser = PayeeNormaliser::create(
+            &[PayeeAlias {
+                name: "Woolworths".to_string(),
+                patterns: vec!["(?i)^woolworths".to_string()],
+            }],
+            true,
+        )
+        .unwrap();
+
+        let mut purchase = transaction("tx1", "Woolies Metro", "-4.50");
+        purchase.attributes.raw_text = Some("WOOLWORTHS METRO 1234 SYDNEY NSW".to_string());
+        assert_eq!(
+            normaliser.normalise(&purchase).as_deref(),
+            Some("Woolworths")
+        );
+
+        let cafe = transaction("tx2", "SQ *CAFE 123 NSW", "-4.50");
+        assert_eq!(normaliser.normalise(&cafe).as_deref(), Some("Cafe"));
+    }
+
+    #[test]
+    fn names_are_left_alone_when_cleaning_is_off() {
+        let normaliser = PayeeNormaliser::create(&[], false).unwrap();
+        assert_eq!(
+            normaliser.normalise(&transaction("tx1", "SQ *CAFE 123 NSW", "-4.50")),
+            None
+        );
+    }
+}
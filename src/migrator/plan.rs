@@ -0,0 +1,201 @@
+use serde_json::Value;
+use tracing::info;
+
+use crate::fire_fly::transaction::{Transaction, TransactionPayload};
+
+/// A single field that would be changed on an existing Firefly transaction.
+#[derive(Debug, Clone)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum PlannedChange {
+    Create {
+        up_bank_id: String,
+        payload: Box<TransactionPayload>,
+    },
+    Update {
+        up_bank_id: String,
+        fire_fly_journal_id: String,
+        changes: Vec<FieldChange>,
+    },
+    Delete {
+        up_bank_id: String,
+        fire_fly_group_id: String,
+    },
+    Drop {
+        up_bank_id: String,
+        payload: Box<TransactionPayload>,
+        rules: Vec<String>,
+    },
+}
+
+/// Collects everything a dry run would have written to Firefly so it can be reviewed before a
+/// real import.
+#[derive(Debug, Default)]
+pub struct Plan {
+    changes: Vec<PlannedChange>,
+}
+
+impl Plan {
+    pub fn add_create(&mut self, up_bank_id: &str, payload: &TransactionPayload) {
+        self.changes.push(PlannedChange::Create {
+            up_bank_id: up_bank_id.to_string(),
+            payload: Box::new(payload.clone()),
+        });
+    }
+
+    pub fn add_update(&mut self, up_bank_id: &str, before: &Transaction, after: &Transaction) {
+        self.changes.push(PlannedChange::Update {
+            up_bank_id: up_bank_id.to_string(),
+            fire_fly_journal_id: after.transaction_journal_id.clone(),
+            changes: diff_transactions(before, after),
+        });
+    }
+
+    pub fn add_delete(&mut self, up_bank_id: &str, fire_fly_group_id: &str) {
+        self.changes.push(PlannedChange::Delete {
+            up_bank_id: up_bank_id.to_string(),
+            fire_fly_group_id: fire_fly_group_id.to_string(),
+        });
+    }
+
+    pub fn add_drop(&mut self, up_bank_id: &str, payload: &TransactionPayload, rules: &[String]) {
+        self.changes.push(PlannedChange::Drop {
+            up_bank_id: up_bank_id.to_string(),
+            payload: Box::new(payload.clone()),
+            rules: rules.to_vec(),
+        });
+    }
+
+    #[cfg(test)]
+    pub fn changes(&self) -> &[PlannedChange] {
+        &self.changes
+    }
+
+    pub fn print(&self) {
+        let mut deposits = 0;
+        let mut withdrawals = 0;
+        let mut transfers = 0;
+        let mut updates = 0;
+        let mut deletes = 0;
+        let mut drops = 0;
+
+        info!("Dry run plan, no changes have been made to Firefly");
+        for change in &self.changes {
+            match change {
+                PlannedChange::Create {
+                    up_bank_id,
+                    payload,
+                } => {
+                    match payload.transaction_type.as_str() {
+                        "deposit" => deposits += 1,
+                        "withdrawal" => withdrawals += 1,
+                        "transfer" => transfers += 1,
+                        _ => {}
+                    }
+                    info!(
+                        "CREATE {} ({}): {} {} on {}, \"{}\", from: {}, to: {}, category: {}, tags: [{}]",
+                        payload.transaction_type,
+                        up_bank_id,
+                        payload.amount,
+                        payload.currency_code.as_deref().unwrap_or_default(),
+                        payload.date,
+                        payload.description,
+                        account_label(&payload.source_id, &payload.source_name),
+                        account_label(&payload.destination_id, &payload.destination_name),
+                        payload.category_name.as_deref().unwrap_or("none"),
+                        payload.tags.join(", ")
+                    );
+                }
+                PlannedChange::Update {
+                    up_bank_id,
+                    fire_fly_journal_id,
+                    changes,
+                } => {
+                    updates += 1;
+                    info!(
+                        "UPDATE journal {} ({}): {} field(s) changed",
+                        fire_fly_journal_id,
+                        up_bank_id,
+                        changes.len()
+                    );
+                    for change in changes {
+                        info!(
+                            "    {}: {} -> {}",
+                            change.field, change.before, change.after
+                        );
+                    }
+                }
+                PlannedChange::Delete {
+                    up_bank_id,
+                    fire_fly_group_id,
+                } => {
+                    deletes += 1;
+                    info!(
+                        "DELETE transaction group {} ({}), deleted in Up Bank",
+                        fire_fly_group_id, up_bank_id
+                    );
+                }
+                PlannedChange::Drop {
+                    up_bank_id,
+                    payload,
+                    rules,
+                } => {
+                    drops += 1;
+                    info!(
+                        "DROP {} ({}): {} {} on {}, \"{}\", dropped by rules: {}",
+                        payload.transaction_type,
+                        up_bank_id,
+                        payload.amount,
+                        payload.currency_code.as_deref().unwrap_or_default(),
+                        payload.date,
+                        payload.description,
+                        rules.join(", ")
+                    );
+                }
+            }
+        }
+        info!(
+            "Dry run summary, {} deposits, {} withdrawals, {} transfers would be created, {} transactions would be updated, {} deleted and {} dropped by rules",
+            deposits, withdrawals, transfers, updates, deletes, drops
+        );
+    }
+}
+
+fn account_label(id: &Option<String>, name: &Option<String>) -> String {
+    match (id, name) {
+        (Some(id), _) => format!("Firefly account {id}"),
+        (None, Some(name)) => name.clone(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Compares the serialised form of both transactions so that any field the migrator changes is
+/// reported, not just the ones it currently knows about.
+fn diff_transactions(before: &Transaction, after: &Transaction) -> Vec<FieldChange> {
+    let (Ok(Value::Object(before)), Ok(Value::Object(after))) =
+        (serde_json::to_value(before), serde_json::to_value(after))
+    else {
+        return vec![];
+    };
+
+    after
+        .iter()
+        .filter_map(|(field, after_value)| {
+            let before_value = before.get(field).unwrap_or(&Value::Null);
+            if before_value == after_value {
+                None
+            } else {
+                Some(FieldChange {
+                    field: field.clone(),
+                    before: before_value.to_string(),
+                    after: after_value.to_string(),
+                })
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,373 @@
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::debug;
+
+use super::category_map::FireFlyReference;
+use crate::{fire_fly::transaction::TransactionPayload, up_bank};
+
+#[derive(Deserialize, Debug, Default)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+/// A regular expression as written in the rules file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        Ok(Self(Regex::new(&value)?))
+    }
+}
+
+/// Every condition that is set has to match for a rule to apply, a field Up Bank left empty never
+/// matches.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Conditions {
+    pub description: Option<Pattern>,
+    pub raw_text: Option<Pattern>,
+    pub message: Option<Pattern>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Actions {
+    pub payee: Option<String>,
+    pub category: Option<String>,
+    pub budget: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub notes: Option<String>,
+    pub destination_account: Option<String>,
+    #[serde(default)]
+    pub drop: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub conditions: Conditions,
+    #[serde(default)]
+    pub actions: Actions,
+    /// Stops any later rules from being evaluated once this one matched.
+    #[serde(default)]
+    pub stop: bool,
+}
+
+/// The combined result of every rule that matched a transaction, later rules override the
+/// actions of earlier ones and tags are accumulated.
+#[derive(Debug, Default)]
+pub struct Evaluation {
+    pub matched_rules: Vec<String>,
+    pub actions: Actions,
+}
+
+impl Evaluation {
+    /// Applies the actions to a transaction about to be imported. Payee and destination account are
+    /// only applied to new transactions so an existing transaction never moves between accounts.
+    pub fn apply(&self, payload: &mut TransactionPayload) {
+        let actions = &self.actions;
+        if let Some(payee) = &actions.payee {
+            match payload.transaction_type.as_str() {
+                "withdrawal" => {
+                    payload.destination_id = None;
+                    payload.destination_name = Some(payee.clone());
+                }
+                "deposit" => {
+                    payload.source_id = None;
+                    payload.source_name = Some(payee.clone());
+                }
+                _ => {}
+            }
+        }
+        if let Some(account) = &actions.destination_account {
+            (payload.destination_id, payload.destination_name) =
+                FireFlyReference::parse(account).into_fields();
+        }
+        self.apply_details(
+            &mut payload.category_id,
+            &mut payload.category_name,
+            &mut payload.budget_id,
+            &mut payload.budget_name,
+            &mut payload.tags,
+            &mut payload.notes,
+        );
+    }
+
+    /// Applies the actions that describe a transaction rather than where the money went, used for
+    /// both new and updated transactions.
+    pub fn apply_details(
+        &self,
+        category_id: &mut Option<String>,
+        category_name: &mut Option<String>,
+        budget_id: &mut Option<String>,
+        budget_name: &mut Option<String>,
+        tags: &mut Vec<String>,
+        notes: &mut Option<String>,
+    ) {
+        let actions = &self.actions;
+        if let Some(category) = &actions.category {
+            (*category_id, *category_name) = FireFlyReference::parse(category).into_fields();
+        }
+        if let Some(budget) = &actions.budget {
+            (*budget_id, *budget_name) = FireFlyReference::parse(budget).into_fields();
+        }
+        for tag in &actions.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        if let Some(rule_notes) = &actions.notes {
+            *notes = Some(rule_notes.clone());
+        }
+    }
+}
+
+/// Transforms applied to transactions before they are sent to Firefly, loaded from a YAML file and
+/// evaluated in the order they are written.
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Loads the rules file, a missing file is treated as having no rules.
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            debug!("Rules file ({}) not found, no rules will be applied", path);
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents, path)
+    }
+
+    fn parse(contents: &str, path: &str) -> Result<Self> {
+        let rules_file: RulesFile = serde_yaml::from_str(contents)
+            .map_err(|e| eyre!("Failed to parse rules file ({}), error: {}", path, e))?;
+
+        for rule in &rules_file.rules {
+            let conditions = &rule.conditions;
+            if conditions.description.is_none()
+                && conditions.raw_text.is_none()
+                && conditions.message.is_none()
+            {
+                return Err(eyre!(
+                    "Rule ({}) in {} does not have any match conditions",
+                    rule.name,
+                    path
+                ));
+            }
+        }
+
+        debug!("Loaded {} rules from {}", rules_file.rules.len(), path);
+        Ok(Self {
+            rules: rules_file.rules,
+        })
+    }
+
+    pub fn evaluate(&self, up_bank_transaction: &up_bank::transactions::Transaction) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        for rule in self.rules.iter().filter(|f| f.matches(up_bank_transaction)) {
+            evaluation.matched_rules.push(rule.name.clone());
+
+            let actions = &mut evaluation.actions;
+            let rule_actions = rule.actions.clone();
+            actions.payee = rule_actions.payee.or(actions.payee.take());
+            actions.category = rule_actions.category.or(actions.category.take());
+            actions.budget = rule_actions.budget.or(actions.budget.take());
+            actions.tags.extend(rule_actions.tags);
+            actions.notes = rule_actions.notes.or(actions.notes.take());
+            actions.destination_account = rule_actions
+                .destination_account
+                .or(actions.destination_account.take());
+            actions.drop |= rule_actions.drop;
+
+            if rule.stop {
+                break;
+            }
+        }
+        evaluation
+    }
+}
+
+impl Rule {
+    fn matches(&self, up_bank_transaction: &up_bank::transactions::Transaction) -> bool {
+        let attributes = &up_bank_transaction.attributes;
+        let is_match = |pattern: &Option<Pattern>, value: Option<&String>| match pattern {
+            Some(Pattern(regex)) => value.map(|f| regex.is_match(f)).unwrap_or(false),
+            None => true,
+        };
+
+        is_match(&self.conditions.description, Some(&attributes.description))
+            && is_match(&self.conditions.raw_text, attributes.raw_text.as_ref())
+            && is_match(&self.conditions.message, attributes.message.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::transactions::fixtures::transaction;
+
+    fn rules(yaml: &str) -> Rules {
+        Rules::parse(yaml, "rules.yaml").unwrap()
+    }
+
+    fn purchase(description: &str, raw_text: Option<&str>) -> up_bank::transactions::Transaction {
+        let mut purchase = transaction("tx1", description, "-4.50");
+        purchase.attributes.raw_text = raw_text.map(|f| f.to_string());
+        purchase
+    }
+
+    #[test]
+    fn rule_without_conditions_is_rejected() {
+        assert!(Rules::parse(
+            "rules:\n  - name: everything\n    match: {}\n    actions:\n      drop: true\n",
+            "rules.yaml"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        assert!(Rules::parse(
+            "rules:\n  - name: broken\n    match:\n      description: \"(\"\n",
+            "rules.yaml"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn every_condition_has_to_match() {
+        let rules = rules(
+            r#"
+rules:
+  - name: woolworths
+    match:
+      description: "^Woolworths"
+      raw_text: "SYDNEY"
+    actions:
+      category: Groceries
+"#,
+        );
+
+        let matched = rules.evaluate(&purchase("Woolworths", Some("WOOLWORTHS 1234 SYDNEY")));
+        assert_eq!(matched.matched_rules, vec!["woolworths"]);
+        assert_eq!(matched.actions.category.as_deref(), Some("Groceries"));
+
+        let other_city = rules.evaluate(&purchase("Woolworths", Some("WOOLWORTHS 1234 PERTH")));
+        assert!(other_city.matched_rules.is_empty());
+
+        // A field Up Bank left empty never matches.
+        let no_raw_text = rules.evaluate(&purchase("Woolworths", None));
+        assert!(no_raw_text.matched_rules.is_empty());
+    }
+
+    #[test]
+    fn later_rules_override_and_tags_accumulate() {
+        let rules = rules(
+            r#"
+rules:
+  - name: food
+    match:
+      description: "Cafe|Woolworths"
+    actions:
+      category: Food
+      budget: Living
+      tags: [food]
+  - name: cafe
+    match:
+      description: "Cafe"
+    actions:
+      category: Coffee
+      tags: [coffee, food]
+"#,
+        );
+
+        let evaluation = rules.evaluate(&purchase("Cafe", None));
+        assert_eq!(evaluation.matched_rules, vec!["food", "cafe"]);
+        assert_eq!(evaluation.actions.category.as_deref(), Some("Coffee"));
+        assert_eq!(evaluation.actions.budget.as_deref(), Some("Living"));
+        assert_eq!(evaluation.actions.tags, vec!["food", "coffee", "food"]);
+        assert!(!evaluation.actions.drop);
+
+        let mut tags = vec!["food".to_string()];
+        let (mut category_id, mut category_name) = (Some("3".to_string()), None);
+        let (mut budget_id, mut budget_name, mut notes) = (None, None, None);
+        evaluation.apply_details(
+            &mut category_id,
+            &mut category_name,
+            &mut budget_id,
+            &mut budget_name,
+            &mut tags,
+            &mut notes,
+        );
+        assert_eq!(category_id, None);
+        assert_eq!(category_name.as_deref(), Some("Coffee"));
+        assert_eq!(budget_name.as_deref(), Some("Living"));
+        assert_eq!(tags, vec!["food", "coffee"]);
+    }
+
+    #[test]
+    fn stop_skips_later_rules() {
+        let rules = rules(
+            r#"
+rules:
+  - name: transfer
+    match:
+      description: "^Transfer"
+    actions:
+      drop: true
+    stop: true
+  - name: everything
+    match:
+      description: "."
+    actions:
+      tags: [seen]
+"#,
+        );
+
+        let evaluation = rules.evaluate(&purchase("Transfer to Savings", None));
+        assert_eq!(evaluation.matched_rules, vec!["transfer"]);
+        assert!(evaluation.actions.drop);
+        assert!(evaluation.actions.tags.is_empty());
+
+        let evaluation = rules.evaluate(&purchase("Cafe", None));
+        assert_eq!(evaluation.matched_rules, vec!["everything"]);
+        assert!(!evaluation.actions.drop);
+    }
+
+    #[test]
+    fn destination_account_overrides_payee() {
+        let rules = rules(
+            r#"
+rules:
+  - name: rent
+    match:
+      message: "rent"
+    actions:
+      payee: Landlord
+      destination_account: "42"
+"#,
+        );
+        let mut rent = purchase("Transfer", None);
+        rent.attributes.message = Some("March rent".to_string());
+
+        let mut payload = TransactionPayload {
+            transaction_type: "withdrawal".to_string(),
+            destination_name: Some("Transfer".to_string()),
+            ..Default::default()
+        };
+        rules.evaluate(&rent).apply(&mut payload);
+        assert_eq!(payload.destination_id.as_deref(), Some("42"));
+        assert_eq!(payload.destination_name, None);
+    }
+}
@@ -0,0 +1,311 @@
+use rust_decimal::Decimal;
+
+use crate::{config::RoundUpBoost, fire_fly::transaction::TransactionPayload, up_bank};
+
+// Description Up Bank gives the Saver side of a round up.
+const ROUND_UP_DESCRIPTION: &str = "Round Up";
+pub const ROUND_UP_TAG: &str = "round-up";
+pub const CASHBACK_TAG: &str = "cashback";
+
+/// The part of an Up Bank transaction a Firefly transaction group was created for. Firefly only
+/// allows splits of the same type in a group, so everything created alongside a purchase gets a
+/// group of its own, linked to the purchase by sharing its group title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitKind {
+    Purchase,
+    RoundUp,
+    Boost,
+    Cashback,
+}
+
+impl SplitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Purchase => "purchase",
+            Self::RoundUp => "round-up",
+            Self::Boost => "boost",
+            Self::Cashback => "cashback",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [Self::Purchase, Self::RoundUp, Self::Boost, Self::Cashback]
+            .into_iter()
+            .find(|f| f.as_str() == value)
+    }
+
+    /// The external id of the group, the purchase keeps the Up Bank id so it is still found when
+    /// searching Firefly for the transaction.
+    pub fn external_id(&self, up_bank_id: &str) -> String {
+        match self {
+            Self::Purchase => up_bank_id.to_string(),
+            _ => format!("{up_bank_id}-{}", self.as_str()),
+        }
+    }
+}
+
+/// A transaction created alongside a purchase.
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub kind: SplitKind,
+    pub payload: TransactionPayload,
+}
+
+/// Builds the transactions for a purchase's round up and any boost on top of it. The round up is
+/// a transfer from the spending account into the round up Saver, the boost is either a deposit
+/// into the Saver paid from `boost_revenue_account` or a transfer like the round up.
+pub fn round_up_splits(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+    purchase: &TransactionPayload,
+    saver_account_id: &str,
+    boost_as: RoundUpBoost,
+    boost_revenue_account: &str,
+) -> Vec<Split> {
+    let round_up = match &up_bank_transaction.attributes.round_up {
+        Some(round_up) => round_up,
+        None => return vec![],
+    };
+
+    let boost = round_up
+        .boost_portion
+        .as_ref()
+        .map(|f| f.magnitude())
+        .unwrap_or_default();
+    let round_up_amount = round_up.amount.magnitude() - boost;
+
+    let split = |kind: SplitKind, description: &str, amount: Decimal| TransactionPayload {
+        transaction_type: "transfer".to_string(),
+        date: purchase.date,
+        amount,
+        description: format!(
+            "{description}, {}",
+            up_bank_transaction.attributes.description
+        ),
+        order: Some(0),
+        currency_code: Some(round_up.amount.currency_code.clone()),
+        source_id: purchase.source_id.clone(),
+        destination_id: Some(saver_account_id.to_string()),
+        tags: vec![ROUND_UP_TAG.to_string()],
+        external_id: Some(kind.external_id(&up_bank_transaction.id)),
+        external_url: purchase.external_url.clone(),
+        ..Default::default()
+    };
+
+    let mut splits = vec![];
+    if round_up_amount > Decimal::ZERO {
+        splits.push(Split {
+            kind: SplitKind::RoundUp,
+            payload: split(SplitKind::RoundUp, ROUND_UP_DESCRIPTION, round_up_amount),
+        });
+    }
+    if boost > Decimal::ZERO {
+        let mut payload = split(SplitKind::Boost, "Boost", boost);
+        if boost_as == RoundUpBoost::Deposit {
+            payload.transaction_type = "deposit".to_string();
+            payload.source_id = None;
+            payload.source_name = Some(boost_revenue_account.to_string());
+        }
+        splits.push(Split {
+            kind: SplitKind::Boost,
+            payload,
+        });
+    }
+    splits
+}
+
+/// True for the Saver side of a round up, which is already covered by the transfer splits created
+/// alongside the purchase.
+pub fn is_round_up_deposit(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+    saver_up_account_id: &str,
+) -> bool {
+    up_bank_transaction.attributes.amount.value > Decimal::ZERO
+        && up_bank_transaction.attributes.description == ROUND_UP_DESCRIPTION
+        && up_bank_transaction
+            .relationships
+            .account
+            .data
+            .as_ref()
+            .map(|f| f.id == saver_up_account_id)
+            .unwrap_or(false)
+}
+
+/// Builds the deposit for the cashback portion of a purchase, paid from the given revenue account
+/// into the account the purchase was made from so it is reported as income.
+pub fn cashback_split(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+    purchase: &TransactionPayload,
+    revenue_account_name: &str,
+) -> Option<Split> {
+    let cashback = up_bank_transaction.attributes.cashback.as_ref()?;
+    let amount = cashback.amount.magnitude();
+    if amount.is_zero() {
+        return None;
+    }
+
+    let payload = TransactionPayload {
+        transaction_type: "deposit".to_string(),
+        date: purchase.date,
+        amount,
+        description: format!(
+            "{}, {}",
+            cashback.description, up_bank_transaction.attributes.description
+        ),
+        order: Some(0),
+        currency_code: Some(cashback.amount.currency_code.clone()),
+        source_name: Some(revenue_account_name.to_string()),
+        destination_id: purchase.source_id.clone(),
+        tags: vec![CASHBACK_TAG.to_string()],
+        external_id: Some(SplitKind::Cashback.external_id(&up_bank_transaction.id)),
+        external_url: purchase.external_url.clone(),
+        ..Default::default()
+    };
+    Some(Split {
+        kind: SplitKind::Cashback,
+        payload,
+    })
+}
+
+/// Up Bank reports a purchase net of any instant cashback, when the cashback is imported as its
+/// own deposit the purchase has to be recorded at its full price for the account to balance.
+pub fn amount_before_cashback(up_bank_transaction: &up_bank::transactions::Transaction) -> Decimal {
+    let cashback = up_bank_transaction
+        .attributes
+        .cashback
+        .as_ref()
+        .map(|f| f.amount.magnitude())
+        .unwrap_or_default();
+    up_bank_transaction.attributes.amount.magnitude() + cashback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::{general::Money, transactions::fixtures::transaction};
+
+    fn money(value: &str) -> Money {
+        Money {
+            currency_code: "AUD".to_string(),
+            value: value.parse().unwrap(),
+            value_in_base_units: 0,
+        }
+    }
+
+    fn purchase_with_round_up(
+        amount: &str,
+        boost: Option<&str>,
+    ) -> up_bank::transactions::Transaction {
+        let mut purchase = transaction("tx1", "Cafe", "-4.50");
+        purchase.attributes.round_up = Some(up_bank::transactions::RoundUpObject {
+            amount: money(amount),
+            boost_portion: boost.map(money),
+        });
+        purchase
+    }
+
+    fn payload() -> TransactionPayload {
+        TransactionPayload {
+            transaction_type: "withdrawal".to_string(),
+            source_id: Some("5".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn purchase_without_round_up_has_no_splits() {
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        let splits = round_up_splits(&purchase, &payload(), "6", RoundUpBoost::Deposit, "Boost");
+        assert!(splits.is_empty());
+    }
+
+    #[test]
+    fn round_up_is_a_transfer_to_the_saver() {
+        let purchase = purchase_with_round_up("-0.50", None);
+        let splits = round_up_splits(&purchase, &payload(), "6", RoundUpBoost::Deposit, "Boost");
+
+        assert_eq!(splits.len(), 1);
+        assert_eq!(splits[0].kind, SplitKind::RoundUp);
+        let round_up = &splits[0].payload;
+        assert_eq!(round_up.transaction_type, "transfer");
+        assert_eq!(round_up.amount, "0.50".parse().unwrap());
+        assert_eq!(round_up.source_id.as_deref(), Some("5"));
+        assert_eq!(round_up.destination_id.as_deref(), Some("6"));
+        assert_eq!(round_up.external_id.as_deref(), Some("tx1-round-up"));
+    }
+
+    #[test]
+    fn boost_can_be_a_deposit_from_the_revenue_account() {
+        let purchase = purchase_with_round_up("-0.70", Some("-0.20"));
+        let splits = round_up_splits(
+            &purchase,
+            &payload(),
+            "6",
+            RoundUpBoost::Deposit,
+            "Up Bank Boost",
+        );
+
+        assert_eq!(splits.len(), 2);
+        assert_eq!(splits[0].payload.amount, "0.50".parse().unwrap());
+        assert_eq!(splits[1].kind, SplitKind::Boost);
+        let boost = &splits[1].payload;
+        assert_eq!(boost.transaction_type, "deposit");
+        assert_eq!(boost.amount, "0.20".parse().unwrap());
+        assert_eq!(boost.source_id, None);
+        assert_eq!(boost.source_name.as_deref(), Some("Up Bank Boost"));
+        assert_eq!(boost.destination_id.as_deref(), Some("6"));
+        assert_eq!(boost.external_id.as_deref(), Some("tx1-boost"));
+    }
+
+    #[test]
+    fn boost_is_a_transfer_by_default() {
+        let purchase = purchase_with_round_up("-0.70", Some("-0.20"));
+        let splits = round_up_splits(&purchase, &payload(), "6", RoundUpBoost::default(), "Boost");
+
+        let boost = &splits[1].payload;
+        assert_eq!(boost.transaction_type, "transfer");
+        assert_eq!(boost.source_id.as_deref(), Some("5"));
+        assert_eq!(boost.source_name, None);
+    }
+
+    #[test]
+    fn split_kind_round_trips() {
+        for kind in [
+            SplitKind::Purchase,
+            SplitKind::RoundUp,
+            SplitKind::Boost,
+            SplitKind::Cashback,
+        ] {
+            assert_eq!(SplitKind::parse(kind.as_str()), Some(kind));
+        }
+        assert_eq!(SplitKind::Purchase.external_id("tx1"), "tx1");
+        assert_eq!(SplitKind::Boost.external_id("tx1"), "tx1-boost");
+    }
+
+    #[test]
+    fn cashback_is_a_deposit_into_the_purchase_account() {
+        let mut purchase = transaction("tx1", "Cafe", "-4.50");
+        purchase.attributes.cashback = Some(up_bank::transactions::Cashback {
+            description: "Instant cashback".to_string(),
+            amount: money("0.45"),
+        });
+
+        let split = cashback_split(&purchase, &payload(), "Up Bank Cashback").unwrap();
+        assert_eq!(split.kind, SplitKind::Cashback);
+        assert_eq!(split.payload.transaction_type, "deposit");
+        assert_eq!(split.payload.amount, "0.45".parse().unwrap());
+        assert_eq!(
+            split.payload.source_name.as_deref(),
+            Some("Up Bank Cashback")
+        );
+        assert_eq!(split.payload.destination_id.as_deref(), Some("5"));
+        assert_eq!(split.payload.external_id.as_deref(), Some("tx1-cashback"));
+        assert_eq!(amount_before_cashback(&purchase), "4.95".parse().unwrap());
+    }
+
+    #[test]
+    fn purchase_without_cashback_has_no_cashback_split() {
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        assert!(cashback_split(&purchase, &payload(), "Up Bank Cashback").is_none());
+        assert_eq!(amount_before_cashback(&purchase), "4.50".parse().unwrap());
+    }
+}
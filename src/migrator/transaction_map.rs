@@ -1,15 +1,20 @@
 use crate::{
+    config::SettledDateField,
     fire_fly::{self, transaction::TransactionPayload},
     up_bank,
 };
+use chrono::{DateTime, FixedOffset};
 use color_eyre::eyre::{eyre, Result};
+use tracing::info;
+
+pub const EXCHANGE_RATE_PREFIX: &str = "Exchange rate: ";
 
 pub enum TransferType {
     Transaction(Box<TransactionPayload>),
     TransactionDuplicate,
 }
 
-use super::account_map;
+use super::{account_map, category_map::CategoryMap};
 
 pub async fn find_up_bank_transaction_in_fire_fly(
     up_bank_transaction: &up_bank::transactions::Transaction,
@@ -25,9 +30,137 @@ pub async fn get_fire_fly_transction_from_up_bank_id(
     up_bank_transaction: &up_bank::transactions::Transaction,
     fire_fly: &fire_fly::FireFly,
 ) -> Result<Vec<fire_fly::transaction::TransactionData>> {
-    fire_fly
+    Ok(fire_fly
         .find_transaction_by_external_id(&up_bank_transaction.id)
-        .await
+        .await?)
+}
+
+/// Brings the amount, foreign amount and date of an imported Firefly transaction in line with its
+/// settled Up Bank transaction, the amount may differ from the original hold. Recording when it
+/// settled is left to `apply_settled_date`.
+pub fn apply_settlement(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+    fire_fly_transaction: &mut fire_fly::transaction::Transaction,
+) {
+    let amount = up_bank_transaction.attributes.amount.magnitude();
+    if let Some(hold_info) = &up_bank_transaction.attributes.hold_info {
+        if hold_info.amount.value_in_base_units
+            != up_bank_transaction.attributes.amount.value_in_base_units
+        {
+            info!(
+                "Transaction({}) was held for {} and settled for {}",
+                up_bank_transaction.id,
+                hold_info.amount.value,
+                up_bank_transaction.attributes.amount.value
+            );
+        }
+    }
+
+    fire_fly_transaction.amount = amount;
+    if let Some(foreign_amount) = &up_bank_transaction.attributes.foreign_amount {
+        fire_fly_transaction.foreign_amount = Some(foreign_amount.magnitude());
+    }
+    fire_fly_transaction.date = up_bank_transaction.attributes.created_at;
+}
+
+/// Records the time a transaction settled as the configured Firefly date, does nothing for
+/// transactions that have not settled yet.
+pub fn apply_settled_date(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+    settled_date: SettledDateField,
+    book_date: &mut Option<DateTime<FixedOffset>>,
+    process_date: &mut Option<DateTime<FixedOffset>>,
+) {
+    let Some(settled_at) = up_bank_transaction.attributes.settled_at else {
+        return;
+    };
+    match settled_date {
+        SettledDateField::BookDate => *book_date = Some(settled_at),
+        SettledDateField::ProcessDate => *process_date = Some(settled_at),
+        SettledDateField::None => {}
+    }
+}
+
+/// Renders the notes template for a transaction. Placeholders are written as `{message}` and a
+/// line is left out entirely when any placeholder on it has no value, eg a purchase has no
+/// message. Returns None when nothing is left.
+pub fn render_notes(
+    template: &str,
+    up_bank_transaction: &up_bank::transactions::Transaction,
+) -> Option<String> {
+    let attributes = &up_bank_transaction.attributes;
+    let card_purchase_method = attributes.card_purchase_method.as_ref();
+    let placeholders = [
+        ("{message}", attributes.message.clone()),
+        ("{raw_text}", attributes.raw_text.clone()),
+        ("{description}", Some(attributes.description.clone())),
+        (
+            "{card_method}",
+            card_purchase_method.map(|f| humanise_card_method(&f.method)),
+        ),
+        (
+            "{card_suffix}",
+            card_purchase_method.and_then(|f| f.card_number_suffix.clone()),
+        ),
+        ("{status}", Some(attributes.status.as_str().to_string())),
+        ("{created_at}", Some(attributes.created_at.to_rfc3339())),
+        (
+            "{settled_at}",
+            attributes.settled_at.map(|f| f.to_rfc3339()),
+        ),
+    ];
+
+    let lines: Vec<String> = template
+        .lines()
+        .filter_map(|line| {
+            let mut rendered = line.to_string();
+            for (placeholder, value) in &placeholders {
+                if rendered.contains(placeholder) {
+                    rendered = rendered.replace(placeholder, value.as_ref()?);
+                }
+            }
+            Some(rendered)
+        })
+        .collect();
+
+    let notes = lines.join("\n").trim().to_string();
+    if notes.is_empty() {
+        None
+    } else {
+        Some(notes)
+    }
+}
+
+/// Describes the exchange rate implied by the amount and foreign amount of a transaction, eg
+/// `Exchange rate: 1 USD = 1.5234 AUD`.
+pub fn exchange_rate_note(
+    up_bank_transaction: &up_bank::transactions::Transaction,
+) -> Option<String> {
+    let attributes = &up_bank_transaction.attributes;
+    let foreign_amount = attributes.foreign_amount.as_ref()?;
+    let amount = attributes.amount.magnitude();
+    let foreign_value = foreign_amount.magnitude();
+    if foreign_value.is_zero() {
+        return None;
+    }
+
+    Some(format!(
+        "{}1 {} = {} {}",
+        EXCHANGE_RATE_PREFIX,
+        foreign_amount.currency_code,
+        (amount / foreign_value).round_dp(6),
+        attributes.amount.currency_code
+    ))
+}
+
+// Up Bank sends the purchase method as SCREAMING_SNAKE_CASE, eg CARD_ON_FILE becomes Card on file.
+fn humanise_card_method(method: &str) -> String {
+    let method = method.replace('_', " ").to_lowercase();
+    let mut chars = method.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => method,
+    }
 }
 
 pub fn is_account_internal(
@@ -43,50 +176,40 @@ pub fn is_account_internal(
 pub fn convert_up_bank_transaction_to_fire_fly(
     up_bank_transaction: &up_bank::transactions::Transaction,
     account_map: &[account_map::AccountMap],
+    category_map: &CategoryMap,
 ) -> Result<TransferType> {
-    let mut fire_fly_transaction = fire_fly::transaction::TransactionPayload::default();
-
-    fire_fly_transaction.external_id = Some(up_bank_transaction.id.clone());
-    fire_fly_transaction.external_url = match &up_bank_transaction.links {
-        Some(links) => links.links_self.as_ref().cloned(),
-        None => None,
+    let mut fire_fly_transaction = fire_fly::transaction::TransactionPayload {
+        external_id: Some(up_bank_transaction.id.clone()),
+        external_url: match &up_bank_transaction.links {
+            Some(links) => links.links_self.as_ref().cloned(),
+            None => None,
+        },
+        amount: up_bank_transaction.attributes.amount.magnitude(),
+        currency_code: Some(up_bank_transaction.attributes.amount.currency_code.clone()),
+        date: up_bank_transaction.attributes.created_at,
+        description: up_bank_transaction.attributes.description.clone(),
+        ..Default::default()
     };
-    fire_fly_transaction.amount = up_bank_transaction
-        .attributes
-        .amount
-        .value
-        .clone()
-        .replace('-', "");
-    fire_fly_transaction.currency_code =
-        Some(up_bank_transaction.attributes.amount.currency_code.clone());
-    fire_fly_transaction.date = up_bank_transaction.attributes.created_at.clone();
-    fire_fly_transaction.description = up_bank_transaction.attributes.description.clone();
-    match &up_bank_transaction.attributes.raw_text {
-        Some(text) => {
-            fire_fly_transaction.description =
-                format!("{}, {}", fire_fly_transaction.description, text)
-        }
-        None => {}
+    if let Some(text) = &up_bank_transaction.attributes.raw_text {
+        fire_fly_transaction.description = format!("{}, {}", fire_fly_transaction.description, text)
     }
 
     fire_fly_transaction.order = Some(0); // Unsure what value should be here, however it is required to be populated
 
-    fire_fly_transaction.category_name = up_bank_transaction
-        .relationships
-        .category
-        .data
-        .as_ref()
-        .map(|f| f.id.clone().replace('-', "_"));
-
-    match &up_bank_transaction.attributes.foreign_amount {
-        Some(foriegn_amount) => {
-            fire_fly_transaction.foreign_amount = Some(foriegn_amount.value.clone().replace('-', ""));
-            fire_fly_transaction.foreign_currency_code = Some(foriegn_amount.currency_code.clone());
-        }
-        None => fire_fly_transaction.foreign_amount = Some("0".to_string()),
+    (
+        fire_fly_transaction.category_id,
+        fire_fly_transaction.category_name,
+    ) = category_map
+        .resolve(up_bank_transaction)
+        .map(|f| f.into_fields())
+        .unwrap_or_default();
+
+    if let Some(foriegn_amount) = &up_bank_transaction.attributes.foreign_amount {
+        fire_fly_transaction.foreign_amount = Some(foriegn_amount.magnitude());
+        fire_fly_transaction.foreign_currency_code = Some(foriegn_amount.currency_code.clone());
     }
 
-    if up_bank_transaction.attributes.amount.value_in_base_units < 0 {
+    if up_bank_transaction.attributes.amount.is_negative() {
         // If its less then $0 then its likely a widthdrawl or a transfer
         fire_fly_transaction.transaction_type = "withdrawal".to_string();
         // If value is less then 0, then the transaction is the source
@@ -169,3 +292,121 @@ pub fn convert_up_bank_transaction_to_fire_fly(
 
     Ok(TransferType::Transaction(Box::new(fire_fly_transaction)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::{
+        general::Money,
+        transactions::{fixtures::transaction, CardPurchaseMethod},
+    };
+
+    const TEMPLATE: &str =
+        "Message: {message}\nPaid with: {card_method} (card ending {card_suffix})";
+
+    #[test]
+    fn renders_every_line_with_values() {
+        let mut purchase = transaction("tx1", "Cafe", "-4.50");
+        purchase.attributes.message = Some("coffee with Sam".to_string());
+        purchase.attributes.card_purchase_method = Some(CardPurchaseMethod {
+            method: "CARD_ON_FILE".to_string(),
+            card_number_suffix: Some("1234".to_string()),
+        });
+
+        assert_eq!(
+            render_notes(TEMPLATE, &purchase).as_deref(),
+            Some("Message: coffee with Sam\nPaid with: Card on file (card ending 1234)")
+        );
+    }
+
+    #[test]
+    fn leaves_out_lines_missing_a_value() {
+        let mut purchase = transaction("tx1", "Cafe", "-4.50");
+        purchase.attributes.card_purchase_method = Some(CardPurchaseMethod {
+            method: "CONTACTLESS".to_string(),
+            card_number_suffix: None,
+        });
+
+        assert_eq!(render_notes(TEMPLATE, &purchase), None);
+        assert_eq!(
+            render_notes("{description} ({status})\n{message}", &purchase).as_deref(),
+            Some("Cafe (SETTLED)")
+        );
+    }
+
+    #[test]
+    fn settled_date_is_only_recorded_when_asked() {
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        for (field, book, process) in [
+            (SettledDateField::None, false, false),
+            (SettledDateField::BookDate, true, false),
+            (SettledDateField::ProcessDate, false, true),
+        ] {
+            let (mut book_date, mut process_date) = (None, None);
+            apply_settled_date(&purchase, field, &mut book_date, &mut process_date);
+            assert_eq!(book_date.is_some(), book, "{field:?}");
+            assert_eq!(process_date.is_some(), process, "{field:?}");
+        }
+        assert_eq!(SettledDateField::default(), SettledDateField::None);
+    }
+
+    fn overseas(amount: &str, foreign_amount: &str) -> up_bank::transactions::Transaction {
+        let mut purchase = transaction("tx1", "Amazon", amount);
+        let value: rust_decimal::Decimal = foreign_amount.parse().unwrap();
+        purchase.attributes.foreign_amount = Some(Money {
+            currency_code: "USD".to_string(),
+            value,
+            value_in_base_units: (value * rust_decimal::Decimal::from(100))
+                .try_into()
+                .unwrap(),
+        });
+        purchase
+    }
+
+    #[test]
+    fn exchange_rate_is_the_amount_per_unit_of_foreign_currency() {
+        assert_eq!(
+            exchange_rate_note(&overseas("-152.34", "-100.00")).as_deref(),
+            Some("Exchange rate: 1 USD = 1.5234 AUD")
+        );
+        assert_eq!(
+            exchange_rate_note(&overseas("-10.00", "-3.00")).as_deref(),
+            Some("Exchange rate: 1 USD = 3.333333 AUD")
+        );
+    }
+
+    #[test]
+    fn exchange_rate_needs_a_foreign_amount() {
+        assert_eq!(
+            exchange_rate_note(&transaction("tx1", "Cafe", "-4.50")),
+            None
+        );
+        assert_eq!(exchange_rate_note(&overseas("-4.50", "0.00")), None);
+    }
+
+    #[test]
+    fn foreign_amount_and_currency_are_converted() {
+        let account_map = vec![account_map::AccountMap::create(
+            "acc1".to_string(),
+            "1".to_string(),
+        )];
+        let category_map = CategoryMap::create(&Default::default(), &Default::default(), &[]);
+
+        let TransferType::Transaction(payload) = convert_up_bank_transaction_to_fire_fly(
+            &overseas("-15.23", "-10.00"),
+            &account_map,
+            &category_map,
+        )
+        .unwrap() else {
+            panic!("an overseas purchase should be imported");
+        };
+
+        assert_eq!(payload.amount.to_string(), "15.23");
+        assert_eq!(payload.currency_code.as_deref(), Some("AUD"));
+        assert_eq!(
+            payload.foreign_amount.map(|f| f.to_string()).as_deref(),
+            Some("10.00")
+        );
+        assert_eq!(payload.foreign_currency_code.as_deref(), Some("USD"));
+    }
+}
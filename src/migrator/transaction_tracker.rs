@@ -1,12 +1,17 @@
+use chrono::Utc;
 use color_eyre::eyre::{eyre, Result};
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
-use tracing::{debug, error, warn};
+use std::path::Path;
+use tracing::{debug, error, info};
 
+use super::content_hash::{calculate_hash, calculate_legacy_hash, HASH_VERSION};
+use super::splits::SplitKind;
+use crate::state_store::{StateStore, STATE_STORE_PATH};
 use crate::up_bank;
 
+const LEGACY_CSV_PATH: &str = "./config/transaction.csv";
+
 pub enum Status {
     NotFound,      // Not found at all
     FoundExact,    // Found key and hash was identical
@@ -20,142 +25,694 @@ pub enum TransactionType {
     Transfer = 2,
     TransferDuplicate = 3, // An internal transfer has two transactions, in and out but firefly only wants one. This indicates that the recv transfer was intetionally not imported.
     Duplicate = 4,
+    Dropped = 5, // Dropped by a rule before it was sent to Firefly.
 }
 
 impl TransactionType {
     pub fn string_to_enum(value: &str) -> TransactionType {
         if value == "transfer" {
             Self::Transfer
-        } else if value == "withdrawl" {
+        } else if value == "withdrawal" {
             Self::Withdrawal
         } else {
             Self::Deposit
         }
     }
+
+    pub fn from_i64(value: i64) -> Option<TransactionType> {
+        match value {
+            0 => Some(Self::Deposit),
+            1 => Some(Self::Withdrawal),
+            2 => Some(Self::Transfer),
+            3 => Some(Self::TransferDuplicate),
+            4 => Some(Self::Duplicate),
+            5 => Some(Self::Dropped),
+            _ => None,
+        }
+    }
+}
+
+const TRACKED_TRANSACTION_QUERY: &str = "SELECT up_bank_id, fire_fly_journal_id, transaction_type, up_bank_account_id, up_bank_created_at
+     FROM transactions WHERE hash IS NOT NULL";
+
+fn tracked_transaction_from_row(row: &rusqlite::Row) -> rusqlite::Result<TrackedTransaction> {
+    Ok(TrackedTransaction {
+        up_bank_id: row.get(0)?,
+        fire_fly_journal_id: row.get(1)?,
+        transaction_type: row
+            .get::<_, Option<i64>>(2)?
+            .and_then(TransactionType::from_i64),
+        up_bank_account_id: row.get(3)?,
+        up_bank_created_at: row.get(4)?,
+    })
+}
+
+/// A transaction that has been imported and not yet deleted.
+#[derive(Debug, Clone)]
+pub struct TrackedTransaction {
+    pub up_bank_id: String,
+    pub fire_fly_journal_id: Option<String>,
+    pub transaction_type: Option<TransactionType>,
+    pub up_bank_account_id: Option<String>,
+    pub up_bank_created_at: Option<String>,
 }
 
+/// A Firefly transaction group created for part of an Up Bank transaction.
+#[derive(Debug, Clone)]
+pub struct FireFlyGroup {
+    pub split: SplitKind,
+    pub group_id: String,
+    pub journal_id: String,
+}
+
+// Row layout of the csv file used before the state store existed, only read during migration.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct TransactionHash {
     pub id: String,
     pub transaction_type: TransactionType,
     pub hash: u64,
 }
+
+/// Tracks every Up Bank transaction that has been processed along with a hash of its contents,
+/// each change is committed to the state store as soon as it is made.
 pub struct TransactionHashData {
-    transaction_map: HashMap<String, TransactionHash>,
+    store: StateStore,
 }
 
-impl TransactionHash {
-    fn new(id: String, transaction_type: TransactionType, hash: u64) -> Self {
-        Self {
-            id,
-            transaction_type,
-            hash,
+impl TransactionHashData {
+    pub fn open(read_only: bool) -> Result<Self> {
+        let store = StateStore::open(STATE_STORE_PATH, read_only)?;
+        let tracker = Self { store };
+        if Path::new(LEGACY_CSV_PATH).exists() {
+            tracker.migrate_csv(read_only)?;
         }
+        Ok(tracker)
     }
-}
 
-impl Drop for TransactionHashData {
-    fn drop(&mut self) {
-        let wtr = csv::Writer::from_path("./config/transaction.csv");
-        match wtr {
-            Ok(mut wtr) => self.transaction_map.iter().for_each(move |f| {
-                match wtr.serialize(TransactionHash::new(
-                    f.0.to_string(),
-                    f.1.transaction_type,
-                    f.1.hash,
-                )) {
-                    Ok(_) => {}
-                    Err(err) => error!("Failed to serialize transaction to csv file: {}", err),
-                }
-            }),
-            Err(err) => error!("Failed to output transaction data to csv file: {}", err),
+    /// A tracker backed by a fresh in-memory store.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            store: StateStore::open(":memory:", false).unwrap(),
         }
     }
-}
 
-impl TransactionHashData {
-    pub fn open() -> Self {
-        let mut transaction_vector: Vec<TransactionHash> = vec![];
-        match csv::Reader::from_path("./config/transaction.csv") {
-            Ok(mut rdr) => {
-                for result in rdr.deserialize() {
-                    match result {
-                        Ok(value) => transaction_vector.push(value),
-                        Err(err) => error!("Failed to deserialise csv value: {}", err),
-                    }
+    /// Imports the transactions tracked by older versions into the state store, the csv file is
+    /// renamed afterwards so it is only migrated once.
+    fn migrate_csv(&self, read_only: bool) -> Result<()> {
+        info!("Migrating {} into the state store", LEGACY_CSV_PATH);
+        let mut rdr = csv::Reader::from_path(LEGACY_CSV_PATH)?;
+        let connection = self.store.connection();
+        let transaction = connection.unchecked_transaction()?;
+        let mut migrated = 0;
+        for result in rdr.deserialize::<TransactionHash>() {
+            match result {
+                Ok(value) => {
+                    migrated += transaction.execute(
+                        "INSERT OR IGNORE INTO transactions (up_bank_id, transaction_type, hash)
+                         VALUES (?1, ?2, ?3)",
+                        params![
+                            value.id,
+                            value.transaction_type as i64,
+                            value.hash.to_string()
+                        ],
+                    )?;
                 }
+                Err(err) => error!("Failed to deserialise csv value: {}", err),
             }
-            Err(err) => error!("Failed to open file, got the following error: {}", err),
         }
-        let mut transaction_map = HashMap::new();
-        transaction_vector.into_iter().for_each(|f| {
-            if let Some(new_val) = transaction_map.insert(f.id.clone(), f) {
-                error!("Key already in map, updated value to: {}", new_val.id)
-            }
-        });
-        Self { transaction_map }
+        transaction.commit()?;
+
+        if read_only {
+            debug!(
+                "State store is read only, leaving {} in place",
+                LEGACY_CSV_PATH
+            );
+        } else {
+            std::fs::rename(LEGACY_CSV_PATH, format!("{LEGACY_CSV_PATH}.migrated"))?;
+        }
+        info!("Migrated {} transactions from csv", migrated);
+        Ok(())
     }
 
-    pub fn find_transaction(&mut self, transaction: &up_bank::transactions::Transaction) -> Status {
-        let hash = calculate_hash(&transaction);
-        match self.transaction_map.get(&transaction.id) {
-            Some(hash_val) => {
-                if hash_val.hash == hash {
+    pub fn find_transaction(
+        &mut self,
+        transaction: &up_bank::transactions::Transaction,
+    ) -> Result<Status> {
+        let stored_hash: Option<(Option<String>, i64)> = self
+            .store
+            .connection()
+            .query_row(
+                "SELECT hash, hash_version FROM transactions WHERE up_bank_id = ?1",
+                params![transaction.id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        // A row without a hash only records a failed import.
+        let (stored_hash, hash_version) = match stored_hash {
+            Some((Some(stored_hash), hash_version)) => (stored_hash, hash_version),
+            _ => return Ok(Status::NotFound),
+        };
+
+        if hash_version != HASH_VERSION {
+            return Ok(
+                if self.rehash(transaction, &stored_hash, hash_version, false)? {
                     Status::FoundExact
                 } else {
                     Status::FoundNotExact
-                }
-            }
-            None => Status::NotFound,
+                },
+            );
         }
+
+        Ok(if stored_hash == calculate_hash(transaction) {
+            Status::FoundExact
+        } else {
+            Status::FoundNotExact
+        })
     }
 
-    pub fn add_transaction(
+    /// Upgrades a hash written by an older version of the importer, returns false when the
+    /// transaction no longer matches the old hash and should be updated in Firefly instead. With
+    /// `assume_unchanged` the old hash is not verified, for when it can no longer be reproduced.
+    fn rehash(
         &mut self,
         transaction: &up_bank::transactions::Transaction,
-        transaction_type: TransactionType,
-    ) {
-        let hash = calculate_hash(&transaction);
-        if let Some(hash_val) = self.transaction_map.get(&transaction.id) {
-            if hash_val.hash == hash {
-                debug!("Transaction found with same hash, no update");
-                return;
-            }
+        stored_hash: &str,
+        hash_version: i64,
+        assume_unchanged: bool,
+    ) -> Result<bool> {
+        let matches = assume_unchanged
+            || match hash_version {
+                0 => stored_hash == calculate_legacy_hash(transaction),
+                _ => false,
+            };
+        if !matches {
+            debug!(
+                "Transaction({}) did not match its version {} hash",
+                transaction.id, hash_version
+            );
+            return Ok(false);
         }
-        if let Some(new_val) = self.transaction_map.insert(
-            transaction.id.clone(),
-            TransactionHash::new(transaction.id.clone(), transaction_type, hash),
-        ) {
-            warn!(
-                "Transaction id({}) was already found, updated hash to: {:?}",
-                transaction.id, new_val
+
+        self.store.connection().execute(
+            "UPDATE transactions SET hash = ?2, hash_version = ?3 WHERE up_bank_id = ?1",
+            params![transaction.id, calculate_hash(transaction), HASH_VERSION],
+        )?;
+        debug!(
+            "Transaction({}) re-hashed from version {} to {}",
+            transaction.id, hash_version, HASH_VERSION
+        );
+        Ok(true)
+    }
+
+    /// Re-hashes a tracked transaction that still uses an older hash version, returns true when
+    /// the stored hash was upgraded. Firefly is never contacted.
+    ///
+    /// The legacy hash depends on the toolchain that built the importer, so after an upgrade it
+    /// never matches. `assume_unchanged` trusts that Firefly is up to date and upgrades the hash
+    /// without verifying it.
+    pub fn migrate_hash(
+        &mut self,
+        transaction: &up_bank::transactions::Transaction,
+        assume_unchanged: bool,
+    ) -> Result<bool> {
+        let stored_hash: Option<(Option<String>, i64)> = self
+            .store
+            .connection()
+            .query_row(
+                "SELECT hash, hash_version FROM transactions WHERE up_bank_id = ?1",
+                params![transaction.id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
             )
+            .optional()?;
+
+        match stored_hash {
+            Some((Some(stored_hash), hash_version)) if hash_version != HASH_VERSION => {
+                self.rehash(transaction, &stored_hash, hash_version, assume_unchanged)
+            }
+            _ => Ok(false),
         }
     }
 
+    pub fn add_transaction(
+        &mut self,
+        transaction: &up_bank::transactions::Transaction,
+        transaction_type: TransactionType,
+        fire_fly_journal_id: Option<&str>,
+    ) -> Result<()> {
+        let hash = calculate_hash(transaction);
+        let now = Utc::now().to_rfc3339();
+        self.store.connection().execute(
+            "INSERT INTO transactions (up_bank_id, fire_fly_journal_id, transaction_type, hash, hash_version, first_imported_at, last_imported_at, last_error)
+             VALUES (?1, ?2, ?3, ?4, ?6, ?5, ?5, NULL)
+             ON CONFLICT (up_bank_id) DO UPDATE SET
+                fire_fly_journal_id = COALESCE(excluded.fire_fly_journal_id, fire_fly_journal_id),
+                transaction_type = excluded.transaction_type,
+                hash = excluded.hash,
+                hash_version = excluded.hash_version,
+                first_imported_at = COALESCE(first_imported_at, excluded.first_imported_at),
+                last_imported_at = excluded.last_imported_at,
+                last_error = NULL
+             WHERE transactions.hash IS NOT excluded.hash",
+            params![
+                transaction.id,
+                fire_fly_journal_id,
+                transaction_type as i64,
+                hash,
+                now,
+                HASH_VERSION
+            ],
+        )?;
+        self.record_up_bank_details(transaction)?;
+        Ok(())
+    }
+
     pub fn update_transaction(
         &mut self,
         transaction: &up_bank::transactions::Transaction,
+        fire_fly_journal_id: &str,
     ) -> Result<()> {
-        let hash = calculate_hash(&transaction);
+        let hash = calculate_hash(transaction);
+        let updated = self.store.connection().execute(
+            "UPDATE transactions
+             SET hash = ?2, hash_version = ?5, fire_fly_journal_id = ?3, last_imported_at = ?4, last_error = NULL
+             WHERE up_bank_id = ?1",
+            params![
+                transaction.id,
+                hash,
+                fire_fly_journal_id,
+                Utc::now().to_rfc3339(),
+                HASH_VERSION
+            ],
+        )?;
 
-        let mut current_val = self
-            .transaction_map
-            .get(&transaction.id)
-            .ok_or(eyre!("Should have had a value when calling update"))?
-            .clone();
+        if updated == 0 {
+            return Err(eyre!("Should have had a value when calling update"));
+        }
+        self.record_up_bank_details(transaction)?;
+        Ok(())
+    }
+
+    // Keeps the account and creation time of the Up Bank transaction so that deletions can be
+    // detected within a date range without asking Up Bank.
+    fn record_up_bank_details(
+        &self,
+        transaction: &up_bank::transactions::Transaction,
+    ) -> Result<()> {
+        self.store.connection().execute(
+            "UPDATE transactions SET up_bank_account_id = ?2, up_bank_created_at = ?3
+             WHERE up_bank_id = ?1",
+            params![
+                transaction.id,
+                transaction
+                    .relationships
+                    .account
+                    .data
+                    .as_ref()
+                    .map(|f| f.id.clone()),
+                transaction.attributes.created_at.to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
 
-        current_val.hash = hash;
+    /// Returns every imported transaction that has not been deleted.
+    pub fn get_tracked_transactions(&self) -> Result<Vec<TrackedTransaction>> {
+        let mut statement = self.store.connection().prepare(&format!(
+            "{TRACKED_TRANSACTION_QUERY} AND deleted_at IS NULL"
+        ))?;
+        let transactions = statement
+            .query_map([], tracked_transaction_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(transactions)
+    }
+
+    pub fn get_tracked_transaction(&self, up_bank_id: &str) -> Result<Option<TrackedTransaction>> {
+        Ok(self
+            .store
+            .connection()
+            .query_row(
+                &format!("{TRACKED_TRANSACTION_QUERY} AND deleted_at IS NULL AND up_bank_id = ?1"),
+                params![up_bank_id],
+                tracked_transaction_from_row,
+            )
+            .optional()?)
+    }
 
-        self.transaction_map
-            .insert(transaction.id.clone(), current_val);
+    /// Flags a transaction as deleted in Up Bank so it is no longer reconciled.
+    pub fn mark_deleted(&mut self, up_bank_id: &str) -> Result<()> {
+        self.store.connection().execute(
+            "UPDATE transactions SET deleted_at = ?2 WHERE up_bank_id = ?1",
+            params![up_bank_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
 
+    /// Records a Firefly transaction group as soon as it is created, so an import that fails
+    /// part way through carries on from where it stopped instead of creating it again.
+    pub fn record_group(
+        &mut self,
+        up_bank_id: &str,
+        split: SplitKind,
+        group_id: &str,
+        journal_id: &str,
+    ) -> Result<()> {
+        self.store.connection().execute(
+            "INSERT OR REPLACE INTO fire_fly_groups (up_bank_id, split, group_id, journal_id)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![up_bank_id, split.as_str(), group_id, journal_id],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the Firefly transaction groups created for an Up Bank transaction.
+    pub fn get_groups(&self, up_bank_id: &str) -> Result<Vec<FireFlyGroup>> {
+        let mut statement = self.store.connection().prepare(
+            "SELECT split, group_id, journal_id FROM fire_fly_groups WHERE up_bank_id = ?1",
+        )?;
+        let groups = statement
+            .query_map(params![up_bank_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .filter_map(|(split, group_id, journal_id)| {
+                Some(FireFlyGroup {
+                    split: SplitKind::parse(&split)?,
+                    group_id,
+                    journal_id,
+                })
+            })
+            .collect();
+        Ok(groups)
+    }
+
+    pub fn remove_group(&mut self, up_bank_id: &str, split: SplitKind) -> Result<()> {
+        self.store.connection().execute(
+            "DELETE FROM fire_fly_groups WHERE up_bank_id = ?1 AND split = ?2",
+            params![up_bank_id, split.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Records why a transaction could not be processed, the stored hash is left alone so that
+    /// the transaction is attempted again on the next run.
+    pub fn record_error(&mut self, up_bank_id: &str, error: &str) -> Result<()> {
+        self.store.connection().execute(
+            "INSERT INTO transactions (up_bank_id, last_error) VALUES (?1, ?2)
+             ON CONFLICT (up_bank_id) DO UPDATE SET last_error = excluded.last_error",
+            params![up_bank_id, error],
+        )?;
         Ok(())
     }
 }
 
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::transactions::fixtures::transaction;
+
+    fn tracker() -> TransactionHashData {
+        TransactionHashData {
+            store: StateStore::open(":memory:", false).unwrap(),
+        }
+    }
+
+    fn row(tracker: &TransactionHashData, up_bank_id: &str) -> (Option<String>, String, String) {
+        tracker
+            .store
+            .connection()
+            .query_row(
+                "SELECT fire_fly_journal_id, first_imported_at, last_imported_at FROM transactions WHERE up_bank_id = ?1",
+                params![up_bank_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap()
+    }
+
+    fn last_error(tracker: &TransactionHashData, up_bank_id: &str) -> Option<String> {
+        tracker
+            .store
+            .connection()
+            .query_row(
+                "SELECT last_error FROM transactions WHERE up_bank_id = ?1",
+                params![up_bank_id],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn added_transaction_is_found_until_it_changes() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::NotFound
+        ));
+
+        tracker
+            .add_transaction(&purchase, TransactionType::Withdrawal, Some("10"))
+            .unwrap();
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::FoundExact
+        ));
+
+        let changed = transaction("tx1", "Cafe", "-5.00");
+        assert!(matches!(
+            tracker.find_transaction(&changed).unwrap(),
+            Status::FoundNotExact
+        ));
+    }
+
+    #[test]
+    fn adding_an_identical_transaction_leaves_the_row_alone() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        tracker
+            .add_transaction(&purchase, TransactionType::Withdrawal, Some("10"))
+            .unwrap();
+        let before = row(&tracker, "tx1");
+
+        tracker
+            .add_transaction(&purchase, TransactionType::Duplicate, Some("11"))
+            .unwrap();
+
+        assert_eq!(row(&tracker, "tx1"), before);
+        let tracked = tracker.get_tracked_transaction("tx1").unwrap().unwrap();
+        assert_eq!(tracked.transaction_type, Some(TransactionType::Withdrawal));
+    }
+
+    #[test]
+    fn adding_a_changed_transaction_keeps_its_journal_and_first_import() {
+        let mut tracker = tracker();
+        tracker
+            .add_transaction(
+                &transaction("tx1", "Cafe", "-4.50"),
+                TransactionType::Withdrawal,
+                Some("10"),
+            )
+            .unwrap();
+        let (_, first_imported_at, _) = row(&tracker, "tx1");
+
+        tracker
+            .add_transaction(
+                &transaction("tx1", "Cafe", "-5.00"),
+                TransactionType::Withdrawal,
+                None,
+            )
+            .unwrap();
+
+        let (journal_id, first, _) = row(&tracker, "tx1");
+        assert_eq!(journal_id.as_deref(), Some("10"));
+        assert_eq!(first, first_imported_at);
+        assert!(matches!(
+            tracker
+                .find_transaction(&transaction("tx1", "Cafe", "-5.00"))
+                .unwrap(),
+            Status::FoundExact
+        ));
+    }
+
+    #[test]
+    fn recorded_error_is_retried_and_cleared_on_import() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        tracker.record_error("tx1", "Firefly returned 500").unwrap();
+
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::NotFound
+        ));
+        assert!(tracker.get_tracked_transactions().unwrap().is_empty());
+
+        tracker
+            .add_transaction(&purchase, TransactionType::Withdrawal, Some("10"))
+            .unwrap();
+        assert_eq!(last_error(&tracker, "tx1"), None);
+    }
+
+    #[test]
+    fn recorded_error_keeps_the_stored_hash() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        tracker
+            .add_transaction(&purchase, TransactionType::Withdrawal, Some("10"))
+            .unwrap();
+
+        tracker.record_error("tx1", "Firefly returned 500").unwrap();
+
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::FoundExact
+        ));
+        assert_eq!(
+            last_error(&tracker, "tx1").as_deref(),
+            Some("Firefly returned 500")
+        );
+    }
+
+    #[test]
+    fn update_replaces_the_hash_and_journal() {
+        let mut tracker = tracker();
+        tracker
+            .add_transaction(
+                &transaction("tx1", "Cafe", "-4.50"),
+                TransactionType::Withdrawal,
+                Some("10"),
+            )
+            .unwrap();
+
+        let settled = transaction("tx1", "Cafe", "-5.00");
+        tracker.update_transaction(&settled, "12").unwrap();
+
+        assert!(matches!(
+            tracker.find_transaction(&settled).unwrap(),
+            Status::FoundExact
+        ));
+        assert_eq!(row(&tracker, "tx1").0.as_deref(), Some("12"));
+    }
+
+    #[test]
+    fn update_of_an_untracked_transaction_fails() {
+        let mut tracker = tracker();
+        assert!(tracker
+            .update_transaction(&transaction("tx1", "Cafe", "-4.50"), "10")
+            .is_err());
+    }
+
+    #[test]
+    fn deleted_transaction_is_no_longer_tracked() {
+        let mut tracker = tracker();
+        for id in ["tx1", "tx2"] {
+            tracker
+                .add_transaction(
+                    &transaction(id, "Cafe", "-4.50"),
+                    TransactionType::Withdrawal,
+                    Some(id),
+                )
+                .unwrap();
+        }
+
+        tracker.mark_deleted("tx1").unwrap();
+
+        let tracked = tracker.get_tracked_transactions().unwrap();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].up_bank_id, "tx2");
+        assert_eq!(tracked[0].up_bank_account_id.as_deref(), Some("acc1"));
+        assert!(tracker.get_tracked_transaction("tx1").unwrap().is_none());
+    }
+
+    #[test]
+    fn legacy_hash_is_upgraded_when_it_still_matches() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        tracker
+            .store
+            .connection()
+            .execute(
+                "INSERT INTO transactions (up_bank_id, transaction_type, hash, hash_version) VALUES ('tx1', 1, ?1, 0)",
+                params![calculate_legacy_hash(&purchase)],
+            )
+            .unwrap();
+
+        assert!(tracker.migrate_hash(&purchase, false).unwrap());
+
+        let (hash, hash_version): (String, i64) = tracker
+            .store
+            .connection()
+            .query_row(
+                "SELECT hash, hash_version FROM transactions WHERE up_bank_id = 'tx1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(hash, calculate_hash(&purchase));
+        assert_eq!(hash_version, HASH_VERSION);
+    }
+
+    #[test]
+    fn unverifiable_legacy_hash_is_only_upgraded_when_assumed_unchanged() {
+        let mut tracker = tracker();
+        let purchase = transaction("tx1", "Cafe", "-4.50");
+        tracker
+            .store
+            .connection()
+            .execute(
+                "INSERT INTO transactions (up_bank_id, transaction_type, hash, hash_version) VALUES ('tx1', 1, '1234', 0)",
+                [],
+            )
+            .unwrap();
+
+        assert!(!tracker.migrate_hash(&purchase, false).unwrap());
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::FoundNotExact
+        ));
+
+        assert!(tracker.migrate_hash(&purchase, true).unwrap());
+        assert!(matches!(
+            tracker.find_transaction(&purchase).unwrap(),
+            Status::FoundExact
+        ));
+        // Already on the latest version, nothing left to upgrade.
+        assert!(!tracker.migrate_hash(&purchase, true).unwrap());
+    }
+
+    #[test]
+    fn groups_are_recorded_per_split() {
+        let mut tracker = tracker();
+        tracker
+            .record_group("tx1", SplitKind::Purchase, "1", "10")
+            .unwrap();
+        tracker
+            .record_group("tx1", SplitKind::RoundUp, "2", "20")
+            .unwrap();
+        tracker
+            .record_group("tx2", SplitKind::Purchase, "3", "30")
+            .unwrap();
+        // Recording a split again replaces it.
+        tracker
+            .record_group("tx1", SplitKind::RoundUp, "4", "40")
+            .unwrap();
+
+        let mut groups = tracker.get_groups("tx1").unwrap();
+        groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].split, SplitKind::Purchase);
+        assert_eq!(groups[1].split, SplitKind::RoundUp);
+        assert_eq!(groups[1].group_id, "4");
+        assert_eq!(groups[1].journal_id, "40");
+
+        tracker.remove_group("tx1", SplitKind::RoundUp).unwrap();
+        let groups = tracker.get_groups("tx1").unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].split, SplitKind::Purchase);
+    }
 }
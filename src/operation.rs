@@ -1,11 +1,19 @@
 use super::Args;
 use crate::config::Config;
+use crate::migrator::account_map::AccountMap;
+use crate::migrator::rules::Rules;
+use crate::migrator::transaction_tracker::TransactionHashData;
 use crate::migrator::Migrator;
-use crate::{fire_fly, up_bank};
+use crate::state_store::{StateStore, STATE_STORE_PATH};
+use crate::{fire_fly, reconcile, up_bank, webhook};
 use chrono::{NaiveDate, Utc};
 use color_eyre::eyre::{eyre, Result};
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::net::SocketAddr;
 use tokio::signal;
-use tracing::{debug, error, info};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
 
 async fn run_import(
     args: &Args,
@@ -13,11 +21,31 @@ async fn run_import(
     fire_fly: &fire_fly::FireFly,
     config: &Config,
     sleep_duration: &std::time::Duration,
-) -> Result<()> {
-    import_data(args, up_bank, fire_fly, config).await?;
-    debug!("Continues import cycle complete, sleeping until next cycle");
-    tokio::time::sleep(*sleep_duration).await;
-    Ok(())
+) {
+    match import_data(args, up_bank, fire_fly, config).await {
+        Ok(()) => {
+            debug!("Continues import cycle complete, sleeping until next cycle");
+            tokio::time::sleep(*sleep_duration).await;
+        }
+        Err(e) => {
+            // Retry sooner than the next scheduled cycle, but never while a circuit breaker is
+            // still holding off requests.
+            let cooldown =
+                std::time::Duration::from_secs(config.http.circuit_breaker_cooldown_seconds);
+            let retry_in = [up_bank.circuit_open_for(), fire_fly.circuit_open_for()]
+                .into_iter()
+                .flatten()
+                .chain([cooldown])
+                .max()
+                .unwrap_or(cooldown)
+                .min(*sleep_duration);
+            error!(
+                "Continues import cycle failed, retrying in {:?}, error: {:?}",
+                retry_in, e
+            );
+            tokio::time::sleep(retry_in).await;
+        }
+    }
 }
 
 pub async fn continues_import(
@@ -57,6 +85,62 @@ pub async fn continues_import(
     Ok(())
 }
 
+pub async fn serve_webhooks(
+    args: &Args,
+    up_bank: up_bank::UpBank,
+    fire_fly: fire_fly::FireFly,
+    config: &Config,
+) -> Result<()> {
+    let mut secret_keys = StateStore::open(STATE_STORE_PATH, false)?.get_webhook_secrets()?;
+    if let Some(secret_key) = &config.up_webhook_secret {
+        secret_keys.push(secret_key.clone());
+    }
+    if secret_keys.is_empty() {
+        return Err(eyre!(
+            "No webhook secret found, create a webhook with the webhook-create action or set upbank_webhook_secret"
+        ));
+    }
+    let address: SocketAddr = config.webhook_listen_address.parse()?;
+
+    let account_map = config.get_accounts(&up_bank, &fire_fly).await?;
+    let mut migrator =
+        Migrator::create(up_bank.clone(), fire_fly, account_map, config, args.dry_run)?;
+    migrator.create_missing_categories().await?;
+
+    let (sender, mut receiver) = mpsc::channel(100);
+    let server = axum::Server::try_bind(&address)?
+        .serve(webhook::router(secret_keys, sender).into_make_service());
+    let server = tokio::spawn(server);
+    info!("Listening for Up Bank webhooks on {}", address);
+
+    loop {
+        tokio::select! {
+            _ = signal::ctrl_c() => {
+                info!("Interupt signal recieved, stopping webhook listener");
+                break;
+            },
+            delivery = receiver.recv() => {
+                let Some(delivery) = delivery else {
+                    break;
+                };
+                let event_id = delivery.event.id.clone();
+                let processed = match webhook::handle_event(delivery.event, &up_bank, &mut migrator).await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        error!("Webhook event({}) failed to process, Up Bank will deliver it again, error: {:?}", event_id, e);
+                        false
+                    }
+                };
+                // The request may have timed out in the meantime, Up Bank delivers it again then.
+                let _ = delivery.processed.send(processed);
+            }
+        }
+    }
+
+    server.abort();
+    Ok(())
+}
+
 pub async fn import_data(
     args: &Args,
     up_bank: &up_bank::UpBank,
@@ -66,6 +150,215 @@ pub async fn import_data(
     let account_map = config.get_accounts(up_bank, fire_fly).await?;
 
     info!("Beginning import...");
+    let (start_date, end_date) = get_date_range(args)?;
+
+    let mut migrator = Migrator::create(
+        up_bank.clone(),
+        fire_fly.clone(),
+        account_map,
+        config,
+        args.dry_run,
+    )?;
+    if args.dry_run {
+        info!("Dry run enabled, Firefly and the transaction tracker will not be modified");
+    }
+    migrator.create_missing_categories().await?;
+    info!("Beginning migration of data");
+    migrator.migrate_transactions(start_date, end_date).await?;
+
+    Ok(())
+}
+
+/// Re-hashes every tracked transaction in the date range that still uses an older hash version,
+/// only Up Bank is queried so Firefly is left untouched.
+pub async fn rehash_transactions(args: &Args, up_bank: &up_bank::UpBank) -> Result<()> {
+    let (start_date, end_date) = get_date_range(args)?;
+    let mut transaction_tracker = TransactionHashData::open(args.dry_run)?;
+
+    let transactions = up_bank.get_all_transactions(start_date, end_date).await?;
+    info!(
+        "Checking {} transactions for outdated hashes",
+        transactions.len()
+    );
+
+    let mut rehashed_counter = 0;
+    for transaction in &transactions {
+        if transaction_tracker.migrate_hash(transaction, args.assume_unchanged)? {
+            rehashed_counter += 1;
+        }
+    }
+
+    info!(
+        "Re-hash complete, {} transactions were upgraded to the latest hash version",
+        rehashed_counter
+    );
+    Ok(())
+}
+
+/// Compares the balance of every mapped account in Up Bank and Firefly, and for accounts that
+/// differ lists where the two ledgers diverge within the date range (the last 30 days by default).
+pub async fn reconcile_accounts(
+    args: &Args,
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    config: &Config,
+) -> Result<()> {
+    let account_map = config.get_accounts(up_bank, fire_fly).await?;
+    let transaction_tracker = TransactionHashData::open(true)?;
+    let (start_date, end_date) = get_date_range(args)?;
+    let start_date = start_date.or_else(|| {
+        Utc::now()
+            .naive_local()
+            .date()
+            .checked_sub_signed(chrono::Duration::days(30))
+    });
+
+    let mut unbalanced_counter = 0;
+    let mut reconciled_counter = 0;
+    for account in &account_map {
+        let comparison = reconcile::compare_balances(up_bank, fire_fly, account).await?;
+        if comparison.is_balanced() {
+            info!(
+                "Up Bank account ({}) and Firefly account ({}) agree, balance: {}",
+                account.up_account_id, account.fire_fly_account_id, comparison.up_bank_balance
+            );
+            if args.mark_reconciled {
+                reconciled_counter +=
+                    mark_reconciled(args, up_bank, fire_fly, account, start_date, end_date).await?;
+            }
+            continue;
+        }
+
+        unbalanced_counter += 1;
+        warn!(
+            "Up Bank account ({}) balance is {} but Firefly account ({}) balance is {}, difference: {}",
+            account.up_account_id,
+            comparison.up_bank_balance,
+            account.fire_fly_account_id,
+            comparison.fire_fly_balance,
+            comparison.difference()
+        );
+
+        let discrepancies = reconcile::find_discrepancies(
+            up_bank,
+            fire_fly,
+            &transaction_tracker,
+            account,
+            config.held_transactions,
+            start_date,
+            end_date,
+        )
+        .await?;
+        if discrepancies.is_empty() {
+            warn!("No differences found in the date range, the balances diverged before it");
+            if args.reconcile_residual {
+                if book_residual(args.dry_run, fire_fly, &comparison, account).await? {
+                    unbalanced_counter -= 1;
+                }
+                if args.mark_reconciled {
+                    reconciled_counter +=
+                        mark_reconciled(args, up_bank, fire_fly, account, start_date, end_date)
+                            .await?;
+                }
+            }
+            continue;
+        }
+        for day in &discrepancies.diverging_days {
+            warn!(
+                "{}: Up Bank total {}, Firefly total {}, running difference {}",
+                day.date, day.up_bank_total, day.fire_fly_total, day.running_difference
+            );
+        }
+        for transaction in &discrepancies.missing {
+            warn!(
+                "Missing in Firefly: Transaction({}) {} {} on {} ({})",
+                transaction.id,
+                transaction.attributes.description,
+                transaction.attributes.amount.value,
+                transaction.attributes.created_at.date_naive(),
+                transaction.attributes.status.as_str()
+            );
+        }
+        for (up_bank_id, count) in &discrepancies.duplicated {
+            warn!(
+                "Duplicated in Firefly: Transaction({}) was imported {} times",
+                up_bank_id, count
+            );
+        }
+        for (up_bank_id, up_bank_amount, fire_fly_amount) in &discrepancies.different_amounts {
+            warn!(
+                "Different amount: Transaction({}) is {} in Up Bank but {} in Firefly",
+                up_bank_id, up_bank_amount, fire_fly_amount
+            );
+        }
+    }
+
+    info!(
+        "Reconciliation complete, {} of {} accounts do not agree",
+        unbalanced_counter,
+        account_map.len()
+    );
+    if args.mark_reconciled {
+        info!("{} transactions marked as reconciled", reconciled_counter);
+    }
+    Ok(())
+}
+
+/// Books the difference between the balances of an account as a reconciliation in Firefly,
+/// returning whether one was created. Nothing is created in a dry run.
+async fn book_residual(
+    dry_run: bool,
+    fire_fly: &fire_fly::FireFly,
+    comparison: &reconcile::BalanceComparison,
+    account: &AccountMap,
+) -> Result<bool> {
+    let entry = reconcile::residual_entry(comparison, account);
+    if dry_run {
+        info!("Would create reconciliation: {:?}", entry);
+        return Ok(false);
+    }
+    fire_fly.submit_new_transaction(&entry).await?;
+    info!(
+        "Created a reconciliation of {} for Firefly account ({})",
+        comparison.difference(),
+        account.fire_fly_account_id
+    );
+    Ok(true)
+}
+
+/// Marks the settled transactions of an account in the date range as reconciled in Firefly,
+/// returning how many were marked.
+async fn mark_reconciled(
+    args: &Args,
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    account: &AccountMap,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<usize> {
+    let journals =
+        reconcile::settled_unreconciled_journals(up_bank, fire_fly, account, start_date, end_date)
+            .await?;
+    for (group_id, journal) in &journals {
+        if args.dry_run {
+            info!(
+                "Would mark transaction({}) {} as reconciled",
+                journal.transaction_journal_id, journal.description
+            );
+            continue;
+        }
+        fire_fly
+            .mark_reconciled(group_id, &journal.transaction_journal_id)
+            .await?;
+        debug!(
+            "Marked transaction({}) as reconciled",
+            journal.transaction_journal_id
+        );
+    }
+    Ok(journals.len())
+}
+
+fn get_date_range(args: &Args) -> Result<(Option<NaiveDate>, Option<NaiveDate>)> {
     let mut start_date = match &args.start_date {
         Some(date_string) => match NaiveDate::parse_from_str(date_string, "%d-%m-%Y") {
             Ok(date_naive) => {
@@ -130,11 +423,7 @@ pub async fn import_data(
         }
     }
 
-    let mut migrator = Migrator::create(up_bank.clone(), fire_fly.clone(), account_map);
-    info!("Beginning migration of data");
-    migrator.migrate_transactions(start_date, end_date).await?;
-
-    Ok(())
+    Ok((start_date, end_date))
 }
 
 pub fn print_out_up_bank_account_info(up_bank: up_bank::UpBank) -> Result<()> {
@@ -146,3 +435,255 @@ pub fn print_out_up_bank_account_info(up_bank: up_bank::UpBank) -> Result<()> {
     }
     Ok(())
 }
+
+/// Offers to create a Firefly asset account for every Up Bank account that is not linked to one
+/// yet. The opening balance is the balance the Up Bank account had when it was opened, worked out
+/// from its current balance and every transaction since, so importing the full history balances.
+pub async fn link_accounts(
+    args: &Args,
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    config: &Config,
+) -> Result<()> {
+    let mut unlinked_accounts = vec![];
+    for account in &up_bank.accounts {
+        if config.accounts.contains_key(&account.id) {
+            info!(
+                "Up Bank account ({}) {} is mapped in settings.yaml",
+                account.id, account.attributes.display_name
+            );
+            continue;
+        }
+        match fire_fly.get_account_by_account_number(&account.id).await? {
+            Some(fire_fly_account) => info!(
+                "Up Bank account ({}) {} is already linked to Firefly ID {}",
+                account.id, account.attributes.display_name, fire_fly_account.id
+            ),
+            None => unlinked_accounts.push(account),
+        }
+    }
+    if unlinked_accounts.is_empty() {
+        info!("Every Up Bank account is already linked to Firefly");
+        return Ok(());
+    }
+
+    let transactions = up_bank.get_all_transactions(None, None).await?;
+    for account in unlinked_accounts {
+        let account_role = match account.attributes.account_type.as_str() {
+            "SAVER" => "savingAsset",
+            _ => "defaultAsset",
+        };
+        let balance_since_opened: Decimal = transactions
+            .iter()
+            .filter(|f| {
+                f.relationships
+                    .account
+                    .data
+                    .as_ref()
+                    .map(|e| e.id == account.id)
+                    .unwrap_or(false)
+            })
+            .map(|f| f.attributes.amount.value)
+            .sum();
+        let payload = fire_fly::accounts::AccountPayload {
+            name: account.attributes.display_name.clone(),
+            account_type: "asset".to_string(),
+            account_role: Some(account_role.to_string()),
+            currency_code: Some(account.attributes.balance.currency_code.clone()),
+            account_number: Some(account.id.clone()),
+            opening_balance: Some(account.attributes.balance.value - balance_since_opened),
+            opening_balance_date: Some(account.attributes.created_at.date_naive()),
+            include_net_worth: Some(true),
+            ..Default::default()
+        };
+
+        info!(
+            "Up Bank account ({}) {} ({}) is not linked, Firefly asset account \"{}\" ({}) with an opening balance of {} on {} can be created",
+            account.id,
+            account.attributes.display_name,
+            account.attributes.account_type,
+            payload.name,
+            account_role,
+            payload.opening_balance.unwrap_or_default(),
+            account.attributes.created_at.date_naive()
+        );
+        if args.dry_run {
+            continue;
+        }
+        if !args.yes && !confirm(&format!("Create Firefly account \"{}\"?", payload.name))? {
+            info!("Skipped Up Bank account ({})", account.id);
+            continue;
+        }
+
+        match fire_fly.create_account(&payload).await {
+            Ok(fire_fly_account) => info!(
+                "Created Firefly account \"{}\" with ID: {}, linked to Up Bank account ({})",
+                payload.name, fire_fly_account.id, account.id
+            ),
+            Err(e) => error!(
+                "Failed to create Firefly account for Up Bank account ({}), error: {:?}",
+                account.id, e
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub async fn create_webhook(args: &Args, up_bank: &up_bank::UpBank) -> Result<()> {
+    let url = args
+        .webhook_url
+        .as_ref()
+        .ok_or_else(|| eyre!("--webhook-url must be set to create a webhook"))?;
+    let store = StateStore::open(STATE_STORE_PATH, false)?;
+
+    let webhook = up_bank
+        .create_webhook(url, args.webhook_description.clone())
+        .await?;
+    store.save_webhook(&webhook)?;
+    info!(
+        "Webhook created, ID: {}, url: {}, secret key saved to the state store",
+        webhook.id, webhook.attributes.url
+    );
+    Ok(())
+}
+
+pub async fn list_webhooks(up_bank: &up_bank::UpBank) -> Result<()> {
+    let store = StateStore::open(STATE_STORE_PATH, true)?;
+    for webhook in up_bank.get_all_webhooks().await? {
+        info!(
+            "Webhook ID: {}, url: {}, description: {}, created at: {}, secret key stored: {}",
+            webhook.id,
+            webhook.attributes.url,
+            webhook.attributes.description.unwrap_or_default(),
+            webhook.attributes.created_at,
+            store.has_webhook(&webhook.id)?
+        );
+    }
+    Ok(())
+}
+
+pub async fn ping_webhook(args: &Args, up_bank: &up_bank::UpBank) -> Result<()> {
+    let id = get_webhook_id(args)?;
+    let event = up_bank.ping_webhook(id).await?;
+    info!("Ping event({}) sent to webhook({})", event.id, id);
+    Ok(())
+}
+
+pub async fn delete_webhook(args: &Args, up_bank: &up_bank::UpBank) -> Result<()> {
+    let id = get_webhook_id(args)?;
+    let store = StateStore::open(STATE_STORE_PATH, false)?;
+    up_bank.delete_webhook(id).await?;
+    store.delete_webhook(id)?;
+    info!("Webhook({}) deleted", id);
+    Ok(())
+}
+
+pub async fn print_webhook_logs(args: &Args, up_bank: &up_bank::UpBank) -> Result<()> {
+    let id = get_webhook_id(args)?;
+    for log in up_bank.get_webhook_logs(id).await? {
+        let (status_code, response_body) = match log.attributes.response {
+            Some(response) => (response.status_code.to_string(), response.body),
+            None => ("none".to_string(), String::new()),
+        };
+        info!(
+            "Delivery at {}, status: {}, response code: {}, response: {}, request: {}",
+            log.attributes.created_at,
+            log.attributes.delivery_status,
+            status_code,
+            response_body,
+            log.attributes.request.body
+        );
+    }
+    Ok(())
+}
+
+/// Shows which rules match an Up Bank transaction and what they would change, nothing is imported.
+pub async fn test_rules(args: &Args, up_bank: &up_bank::UpBank, config: &Config) -> Result<()> {
+    let id = args
+        .transaction_id
+        .as_deref()
+        .ok_or_else(|| eyre!("--transaction-id must be set to test the rules"))?;
+    let rules = Rules::load(&config.rules_file)?;
+    let transaction = up_bank.get_transaction(id).await?;
+    info!(
+        "Transaction({}), description: {}, raw text: {}, message: {}",
+        transaction.id,
+        transaction.attributes.description,
+        transaction.attributes.raw_text.as_deref().unwrap_or("none"),
+        transaction.attributes.message.as_deref().unwrap_or("none")
+    );
+
+    let evaluation = rules.evaluate(&transaction);
+    if evaluation.matched_rules.is_empty() {
+        info!("No rules matched");
+        return Ok(());
+    }
+    for rule in &evaluation.matched_rules {
+        info!("Rule matched: {}", rule);
+    }
+
+    let actions = evaluation.actions;
+    if actions.drop {
+        info!("Transaction would be dropped");
+        return Ok(());
+    }
+    let or_unchanged = |value: Option<String>| value.unwrap_or_else(|| "unchanged".to_string());
+    info!(
+        "Payee: {}, category: {}, budget: {}, destination account: {}, tags: [{}], notes: {}",
+        or_unchanged(actions.payee),
+        or_unchanged(actions.category),
+        or_unchanged(actions.budget),
+        or_unchanged(actions.destination_account),
+        actions.tags.join(", "),
+        or_unchanged(actions.notes)
+    );
+    Ok(())
+}
+
+fn get_webhook_id(args: &Args) -> Result<&str> {
+    args.webhook_id
+        .as_deref()
+        .ok_or_else(|| eyre!("--webhook-id must be set for this action"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fire_fly::fake::FakeFireFly;
+
+    #[tokio::test]
+    async fn residual_is_only_booked_outside_a_dry_run() {
+        let fire_fly = FakeFireFly::start();
+        let account = AccountMap::create("acc1".to_string(), "1".to_string());
+        let comparison = reconcile::BalanceComparison {
+            currency_code: "AUD".to_string(),
+            up_bank_balance: "100.00".parse().unwrap(),
+            fire_fly_balance: "90.00".parse().unwrap(),
+        };
+
+        assert!(
+            !book_residual(true, &fire_fly.client(), &comparison, &account)
+                .await
+                .unwrap()
+        );
+        assert!(fire_fly.writes().is_empty());
+
+        assert!(
+            book_residual(false, &fire_fly.client(), &comparison, &account)
+                .await
+                .unwrap()
+        );
+        assert_eq!(fire_fly.writes(), ["POST /api/v1/transactions"]);
+        let journal = &fire_fly.groups()[0].attributes.transactions[0];
+        assert_eq!(journal.transaction_type, "reconciliation");
+        assert_eq!(journal.amount, "10.00".parse().unwrap());
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{NaiveDate, Utc};
+use color_eyre::eyre::{eyre, Result};
+use rust_decimal::Decimal;
+
+use crate::config::HeldTransactionPolicy;
+use crate::fire_fly::{
+    self,
+    transaction::{Transaction as Journal, TransactionPayload},
+};
+use crate::migrator::{
+    account_map::AccountMap,
+    splits,
+    transaction_tracker::{TransactionHashData, TransactionType},
+};
+use crate::up_bank::{self, transactions::TransactionStatus};
+
+// External id suffixes of splits created alongside the journal of an Up Bank transaction.
+const SPLIT_SUFFIXES: &[&str] = &["-round-up", "-boost", "-cashback"];
+
+/// The balance of an Up Bank account and of the Firefly account it is mapped to.
+pub struct BalanceComparison {
+    pub currency_code: String,
+    pub up_bank_balance: Decimal,
+    pub fire_fly_balance: Decimal,
+}
+
+impl BalanceComparison {
+    pub fn difference(&self) -> Decimal {
+        self.up_bank_balance - self.fire_fly_balance
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.difference().is_zero()
+    }
+}
+
+/// A day on which the transactions of an account add up to a different amount in each ledger.
+pub struct DivergingDay {
+    pub date: NaiveDate,
+    pub up_bank_total: Decimal,
+    pub fire_fly_total: Decimal,
+    pub running_difference: Decimal,
+}
+
+/// Up Bank transactions that were imported incorrectly, or not at all.
+#[derive(Default)]
+pub struct Discrepancies {
+    pub diverging_days: Vec<DivergingDay>,
+    pub missing: Vec<up_bank::transactions::Transaction>,
+    pub duplicated: Vec<(String, usize)>,
+    pub different_amounts: Vec<(String, Decimal, Decimal)>,
+}
+
+impl Discrepancies {
+    pub fn is_empty(&self) -> bool {
+        self.diverging_days.is_empty()
+            && self.missing.is_empty()
+            && self.duplicated.is_empty()
+            && self.different_amounts.is_empty()
+    }
+}
+
+pub async fn compare_balances(
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    account: &AccountMap,
+) -> Result<BalanceComparison> {
+    let up_bank_account = up_bank
+        .accounts
+        .iter()
+        .find(|f| f.id == account.up_account_id)
+        .ok_or_else(|| eyre!("Up Bank account ({}) not found", account.up_account_id))?;
+    let fire_fly_account = fire_fly.get_account(&account.fire_fly_account_id).await?;
+
+    Ok(BalanceComparison {
+        currency_code: up_bank_account.attributes.balance.currency_code.clone(),
+        up_bank_balance: up_bank_account.attributes.balance.value,
+        fire_fly_balance: fire_fly_account.attributes.current_balance,
+    })
+}
+
+async fn account_transactions(
+    up_bank: &up_bank::UpBank,
+    account: &AccountMap,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<Vec<up_bank::transactions::Transaction>> {
+    Ok(up_bank
+        .get_all_transactions(start_date, end_date)
+        .await?
+        .into_iter()
+        .filter(|f| {
+            f.relationships
+                .account
+                .data
+                .as_ref()
+                .map(|e| e.id == account.up_account_id)
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+/// The amount of a journal as seen from the given Firefly account, money leaving it is negative.
+pub fn signed_amount(journal: &Journal, fire_fly_account_id: &str) -> Decimal {
+    if journal.source_id.as_deref() == Some(fire_fly_account_id) {
+        -journal.amount
+    } else {
+        journal.amount
+    }
+}
+
+/// Walks the transactions of an account in both ledgers over a date range, finding the days the
+/// running balances diverge and the transactions that are missing, duplicated or have a different
+/// amount in Firefly.
+pub async fn find_discrepancies(
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    transaction_tracker: &TransactionHashData,
+    account: &AccountMap,
+    held_transactions: HeldTransactionPolicy,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<Discrepancies> {
+    let up_bank_transactions = account_transactions(up_bank, account, start_date, end_date).await?;
+    let journals: Vec<Journal> = fire_fly
+        .get_account_transactions(&account.fire_fly_account_id, start_date, end_date)
+        .await?
+        .into_iter()
+        .flat_map(|f| f.attributes.transactions)
+        .collect();
+
+    compare_ledgers(
+        up_bank_transactions,
+        &journals,
+        transaction_tracker,
+        &account.fire_fly_account_id,
+        held_transactions,
+    )
+}
+
+/// Compares the Up Bank transactions of an account with the journals of the Firefly account.
+/// HELD transactions are left out when the held policy skips them, they are not meant to be in
+/// Firefly yet.
+fn compare_ledgers(
+    up_bank_transactions: Vec<up_bank::transactions::Transaction>,
+    journals: &[Journal],
+    transaction_tracker: &TransactionHashData,
+    fire_fly_account_id: &str,
+    held_transactions: HeldTransactionPolicy,
+) -> Result<Discrepancies> {
+    let up_bank_transactions: Vec<_> = up_bank_transactions
+        .into_iter()
+        .filter(|f| {
+            f.attributes.status != TransactionStatus::Held
+                || held_transactions != HeldTransactionPolicy::Skip
+        })
+        .collect();
+
+    let mut journals_by_id: HashMap<&str, Vec<&Journal>> = HashMap::new();
+    for journal in journals {
+        if let Some(external_id) = journal.external_id.as_deref() {
+            if !SPLIT_SUFFIXES.iter().any(|f| external_id.ends_with(f)) {
+                journals_by_id.entry(external_id).or_default().push(journal);
+            }
+        }
+    }
+
+    let mut discrepancies = Discrepancies {
+        diverging_days: diverging_days(&up_bank_transactions, journals, fire_fly_account_id),
+        ..Default::default()
+    };
+    for transaction in up_bank_transactions {
+        match journals_by_id.get(transaction.id.as_str()) {
+            None => {
+                // The receiving side of a transfer is imported by the sending side and dropped
+                // transactions are never imported.
+                let tracked_type = transaction_tracker
+                    .get_tracked_transaction(&transaction.id)?
+                    .and_then(|f| f.transaction_type);
+                if !matches!(
+                    tracked_type,
+                    Some(TransactionType::TransferDuplicate | TransactionType::Dropped)
+                ) {
+                    discrepancies.missing.push(transaction);
+                }
+            }
+            Some(matching_journals) if matching_journals.len() > 1 => {
+                discrepancies
+                    .duplicated
+                    .push((transaction.id.clone(), matching_journals.len()));
+            }
+            Some(matching_journals) => {
+                let fire_fly_amount = signed_amount(matching_journals[0], fire_fly_account_id);
+                // Purchases with cashback imported as a deposit of its own are at full price.
+                let is_full_price = transaction.attributes.cashback.is_some()
+                    && fire_fly_amount == -splits::amount_before_cashback(&transaction);
+                if fire_fly_amount != transaction.attributes.amount.value && !is_full_price {
+                    discrepancies.different_amounts.push((
+                        transaction.id.clone(),
+                        transaction.attributes.amount.value,
+                        fire_fly_amount,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(discrepancies)
+}
+
+/// Totals both ledgers by day and walks the running balances, returning every day on which they
+/// disagree. Once the balances diverge each following day is listed until they agree again.
+fn diverging_days(
+    up_bank_transactions: &[up_bank::transactions::Transaction],
+    journals: &[Journal],
+    fire_fly_account_id: &str,
+) -> Vec<DivergingDay> {
+    let mut daily_totals: BTreeMap<NaiveDate, (Decimal, Decimal)> = BTreeMap::new();
+    for transaction in up_bank_transactions {
+        let date = transaction.attributes.created_at.date_naive();
+        daily_totals.entry(date).or_default().0 += transaction.attributes.amount.value;
+    }
+    for journal in journals {
+        let date = journal.date.date_naive();
+        daily_totals.entry(date).or_default().1 += signed_amount(journal, fire_fly_account_id);
+    }
+
+    let mut diverging_days = vec![];
+    let mut running_difference = Decimal::ZERO;
+    for (date, (up_bank_total, fire_fly_total)) in daily_totals {
+        running_difference += up_bank_total - fire_fly_total;
+        if !running_difference.is_zero() {
+            diverging_days.push(DivergingDay {
+                date,
+                up_bank_total,
+                fire_fly_total,
+                running_difference,
+            });
+        }
+    }
+    diverging_days
+}
+
+/// Finds the journals of an account that were imported from Up Bank transactions that have since
+/// settled but are not yet marked as reconciled in Firefly, along with the id of their group.
+/// Journals not created by the importer are left alone.
+pub async fn settled_unreconciled_journals(
+    up_bank: &up_bank::UpBank,
+    fire_fly: &fire_fly::FireFly,
+    account: &AccountMap,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<Vec<(String, Journal)>> {
+    let settled_ids: HashSet<String> = account_transactions(up_bank, account, start_date, end_date)
+        .await?
+        .into_iter()
+        .filter(|f| f.attributes.status == TransactionStatus::Settled)
+        .map(|f| f.id)
+        .collect();
+
+    let mut journals = vec![];
+    for group in fire_fly
+        .get_account_transactions(&account.fire_fly_account_id, start_date, end_date)
+        .await?
+    {
+        for journal in group.attributes.transactions {
+            let up_bank_id = journal.external_id.as_deref().map(|external_id| {
+                SPLIT_SUFFIXES
+                    .iter()
+                    .find_map(|f| external_id.strip_suffix(f))
+                    .unwrap_or(external_id)
+            });
+            let is_settled = up_bank_id.map(|f| settled_ids.contains(f)).unwrap_or(false);
+            if is_settled && !journal.reconciled {
+                journals.push((group.id.clone(), journal));
+            }
+        }
+    }
+    Ok(journals)
+}
+
+/// Builds the Firefly reconciliation that books the remaining difference between the two
+/// balances against the Firefly account. Firefly pairs it with its own reconciliation account.
+pub fn residual_entry(comparison: &BalanceComparison, account: &AccountMap) -> TransactionPayload {
+    let difference = comparison.difference();
+    let (source_id, destination_id) = if difference.is_sign_negative() {
+        (Some(account.fire_fly_account_id.clone()), None)
+    } else {
+        (None, Some(account.fire_fly_account_id.clone()))
+    };
+
+    TransactionPayload {
+        transaction_type: "reconciliation".to_string(),
+        date: Utc::now().fixed_offset(),
+        amount: difference.abs(),
+        description: "Reconciliation with Up Bank".to_string(),
+        currency_code: Some(comparison.currency_code.clone()),
+        source_id,
+        destination_id,
+        reconciled: true,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::up_bank::transactions::fixtures;
+
+    fn on(date: &str, id: &str, amount: &str) -> up_bank::transactions::Transaction {
+        let mut transaction = fixtures::transaction(id, "Cafe", amount);
+        transaction.attributes.created_at = format!("{date}T09:30:00+10:00").parse().unwrap();
+        transaction
+    }
+
+    /// A journal of Firefly account 1 imported from the given Up Bank transaction.
+    fn journal(date: &str, external_id: &str, amount: &str) -> Journal {
+        let amount: Decimal = amount.parse().unwrap();
+        let (source_id, destination_id) = if amount.is_sign_negative() {
+            ("1", "2")
+        } else {
+            ("2", "1")
+        };
+        serde_json::from_value(serde_json::json!({
+            "user": "1",
+            "transaction_journal_id": "10",
+            "type": "withdrawal",
+            "date": format!("{date}T09:30:00+10:00"),
+            "currency_symbol": "$",
+            "currency_name": "Australian dollar",
+            "currency_decimal_places": 2,
+            "amount": amount.abs().to_string(),
+            "description": "Cafe",
+            "source_id": source_id,
+            "source_type": "Asset account",
+            "destination_id": destination_id,
+            "destination_type": "Expense account",
+            "reconciled": false,
+            "external_id": external_id,
+            "has_attachments": false
+        }))
+        .unwrap()
+    }
+
+    fn comparison(up_bank_balance: &str, fire_fly_balance: &str) -> BalanceComparison {
+        BalanceComparison {
+            currency_code: "AUD".to_string(),
+            up_bank_balance: up_bank_balance.parse().unwrap(),
+            fire_fly_balance: fire_fly_balance.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn signed_amount_is_negative_when_money_leaves_the_account() {
+        let withdrawal = journal("2023-02-01", "tx1", "-4.50");
+        assert_eq!(signed_amount(&withdrawal, "1"), Decimal::new(-450, 2));
+        assert_eq!(signed_amount(&withdrawal, "2"), Decimal::new(450, 2));
+
+        let deposit = journal("2023-02-01", "tx2", "20.00");
+        assert_eq!(signed_amount(&deposit, "1"), Decimal::new(2000, 2));
+    }
+
+    #[test]
+    fn days_are_listed_from_the_first_divergence_until_the_balances_agree() {
+        let up_bank_transactions = vec![
+            on("2023-02-01", "tx1", "-4.50"),
+            on("2023-02-02", "tx2", "-10.00"),
+            on("2023-02-03", "tx3", "-2.00"),
+            on("2023-02-04", "tx4", "-1.00"),
+        ];
+        // tx2 is missing and the difference is booked by hand on the 4th.
+        let journals = vec![
+            journal("2023-02-01", "tx1", "-4.50"),
+            journal("2023-02-03", "tx3", "-2.00"),
+            journal("2023-02-04", "tx4", "-1.00"),
+            journal("2023-02-04", "manual", "-10.00"),
+        ];
+
+        let days = diverging_days(&up_bank_transactions, &journals, "1");
+        let dates: Vec<String> = days.iter().map(|f| f.date.to_string()).collect();
+        // The 3rd totals the same in both ledgers but the running balances still differ.
+        assert_eq!(dates, ["2023-02-02", "2023-02-03"]);
+        assert_eq!(days[0].up_bank_total, Decimal::new(-1000, 2));
+        assert_eq!(days[0].fire_fly_total, Decimal::ZERO);
+        assert_eq!(days[1].running_difference, Decimal::new(-1000, 2));
+    }
+
+    #[test]
+    fn held_transactions_skipped_by_the_policy_are_not_missing() {
+        let mut held = on("2023-02-02", "tx2", "-10.00");
+        held.attributes.status = TransactionStatus::Held;
+        let up_bank_transactions = vec![on("2023-02-01", "tx1", "-4.50"), held];
+        let journals = vec![journal("2023-02-01", "tx1", "-4.50")];
+        let transaction_tracker = TransactionHashData::in_memory();
+
+        let discrepancies = compare_ledgers(
+            up_bank_transactions.clone(),
+            &journals,
+            &transaction_tracker,
+            "1",
+            HeldTransactionPolicy::Skip,
+        )
+        .unwrap();
+        assert!(discrepancies.is_empty());
+
+        let discrepancies = compare_ledgers(
+            up_bank_transactions,
+            &journals,
+            &transaction_tracker,
+            "1",
+            HeldTransactionPolicy::Import,
+        )
+        .unwrap();
+        assert_eq!(discrepancies.missing.len(), 1);
+        assert_eq!(discrepancies.missing[0].id, "tx2");
+        assert_eq!(discrepancies.diverging_days.len(), 1);
+    }
+
+    #[test]
+    fn residual_entry_books_the_difference_against_the_account() {
+        let account = AccountMap::create("acc1".to_string(), "1".to_string());
+
+        // Firefly is short, the reconciliation pays into the account.
+        let entry = residual_entry(&comparison("100.00", "90.00"), &account);
+        assert_eq!(entry.transaction_type, "reconciliation");
+        assert_eq!(entry.amount, Decimal::new(1000, 2));
+        assert_eq!(entry.source_id, None);
+        assert_eq!(entry.destination_id.as_deref(), Some("1"));
+        assert_eq!(entry.currency_code.as_deref(), Some("AUD"));
+        assert!(entry.reconciled);
+
+        // Firefly has too much, the reconciliation takes it out.
+        let entry = residual_entry(&comparison("90.00", "100.00"), &account);
+        assert_eq!(entry.amount, Decimal::new(1000, 2));
+        assert_eq!(entry.source_id.as_deref(), Some("1"));
+        assert_eq!(entry.destination_id, None);
+    }
+}
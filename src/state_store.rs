@@ -0,0 +1,232 @@
+use color_eyre::eyre::{eyre, Result};
+use rusqlite::{params, Connection, DatabaseName};
+use std::path::Path;
+
+use crate::up_bank::webhooks::Webhook;
+use tracing::{debug, info};
+
+pub const STATE_STORE_PATH: &str = "./config/state.db";
+
+// Each entry upgrades the schema by one version, the current version is kept in `user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE transactions (
+        up_bank_id TEXT PRIMARY KEY NOT NULL,
+        fire_fly_journal_id TEXT,
+        transaction_type INTEGER,
+        hash TEXT,
+        first_imported_at TEXT,
+        last_imported_at TEXT,
+        last_error TEXT
+    );",
+    // Rows written before hashes were versioned used the legacy `DefaultHasher` hash.
+    "ALTER TABLE transactions ADD COLUMN hash_version INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE webhooks (
+        id TEXT PRIMARY KEY NOT NULL,
+        url TEXT NOT NULL,
+        description TEXT,
+        secret_key TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );",
+    "ALTER TABLE transactions ADD COLUMN up_bank_account_id TEXT;
+    ALTER TABLE transactions ADD COLUMN up_bank_created_at TEXT;
+    ALTER TABLE transactions ADD COLUMN deleted_at TEXT;",
+    // Every Firefly transaction group created for an Up Bank transaction, eg its round up.
+    "CREATE TABLE fire_fly_groups (
+        up_bank_id TEXT NOT NULL,
+        split TEXT NOT NULL,
+        group_id TEXT NOT NULL,
+        journal_id TEXT NOT NULL,
+        PRIMARY KEY (up_bank_id, split)
+    );",
+];
+
+/// Persistent state shared between runs, backed by an embedded SQLite database.
+pub struct StateStore {
+    connection: Connection,
+}
+
+impl StateStore {
+    /// Opens the state store at the given path, creating it when missing. A read only store is
+    /// loaded into memory so that nothing written during the run reaches the file on disk.
+    pub fn open(path: &str, read_only: bool) -> Result<Self> {
+        let connection = if read_only {
+            let mut connection = Connection::open_in_memory()?;
+            if Path::new(path).exists() {
+                connection.restore(
+                    DatabaseName::Main,
+                    path,
+                    None::<fn(rusqlite::backup::Progress)>,
+                )?;
+            }
+            debug!("State store ({}) loaded into memory as read only", path);
+            connection
+        } else {
+            Connection::open(path)?
+        };
+
+        let mut store = Self { connection };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Stores a newly created webhook, Up Bank only returns the secret key once so this is the
+    /// only copy of it.
+    pub fn save_webhook(&self, webhook: &Webhook) -> Result<()> {
+        let secret_key = webhook
+            .attributes
+            .secret_key
+            .as_ref()
+            .ok_or_else(|| eyre!("Webhook({}) did not include a secret key", webhook.id))?;
+        self.connection.execute(
+            "INSERT OR REPLACE INTO webhooks (id, url, description, secret_key, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                webhook.id,
+                webhook.attributes.url,
+                webhook.attributes.description,
+                secret_key,
+                webhook.attributes.created_at.to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_webhook_secrets(&self) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare("SELECT secret_key FROM webhooks")?;
+        let secrets = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(secrets)
+    }
+
+    pub fn has_webhook(&self, id: &str) -> Result<bool> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM webhooks WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    pub fn delete_webhook(&self, id: &str) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        let version: usize = self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        if version > MIGRATIONS.len() {
+            return Err(eyre!(
+                "State store schema version {} is newer than this version of the importer supports ({})",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = self.connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+            info!("State store migrated to schema version {}", index + 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(store: &StateStore) -> usize {
+        store
+            .connection()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn new_store_is_migrated_to_the_latest_version() {
+        let store = StateStore::open(":memory:", false).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+
+        // Every column added by a migration is queryable.
+        store
+            .connection()
+            .execute(
+                "INSERT INTO transactions (up_bank_id, hash, hash_version, up_bank_account_id, up_bank_created_at, deleted_at)
+                 VALUES ('tx1', 'abc', 1, 'acc1', '2023-02-01T09:30:00+10:00', NULL)",
+                [],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn migrating_an_older_store_keeps_its_rows() {
+        let mut store = StateStore {
+            connection: Connection::open_in_memory().unwrap(),
+        };
+        store.connection.execute_batch(MIGRATIONS[0]).unwrap();
+        store
+            .connection
+            .pragma_update(None, "user_version", 1)
+            .unwrap();
+        store
+            .connection
+            .execute(
+                "INSERT INTO transactions (up_bank_id, transaction_type, hash) VALUES ('tx1', 1, '42')",
+                [],
+            )
+            .unwrap();
+
+        store.migrate().unwrap();
+
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        let (hash, hash_version, deleted_at): (String, i64, Option<String>) = store
+            .connection
+            .query_row(
+                "SELECT hash, hash_version, deleted_at FROM transactions WHERE up_bank_id = 'tx1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(hash, "42");
+        // Rows that predate versioned hashes are marked as using the legacy hash.
+        assert_eq!(hash_version, 0);
+        assert_eq!(deleted_at, None);
+    }
+
+    #[test]
+    fn migrating_twice_does_nothing() {
+        let mut store = StateStore::open(":memory:", false).unwrap();
+        store.migrate().unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut store = StateStore {
+            connection: Connection::open_in_memory().unwrap(),
+        };
+        store
+            .connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(store.migrate().is_err());
+    }
+
+    #[test]
+    fn read_only_store_without_a_file_starts_empty() {
+        let store = StateStore::open("./does-not-exist/state.db", true).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        assert!(store.get_webhook_secrets().unwrap().is_empty());
+    }
+}
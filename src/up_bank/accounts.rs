@@ -1,7 +1,8 @@
+use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 
 use super::{
-    general::{self, MoneyObject},
+    general::{self, Money},
     pagination::Pagination,
 };
 
@@ -29,9 +30,9 @@ pub struct Attributes {
     pub account_type: String,
     #[serde(rename = "ownershipType")]
     pub ownership_type: String,
-    pub balance: MoneyObject,
+    pub balance: Money,
     #[serde(rename = "createdAt")]
-    pub created_at: String,
+    pub created_at: DateTime<FixedOffset>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
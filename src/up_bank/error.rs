@@ -0,0 +1,131 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::http_client::{ErrorKind, SendError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Why a request to Up Bank failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The personal access token was rejected or has been revoked.
+    Unauthorized,
+    NotFound(String),
+    /// Still rate limited after retrying.
+    RateLimited,
+    /// Any other unsuccessful response, with the details Up Bank gave.
+    Status {
+        status: StatusCode,
+        message: String,
+    },
+    /// Up Bank could not be reached.
+    Transport(SendError),
+    /// The response could not be understood.
+    Decode(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Unauthorized => ErrorKind::Unauthorized,
+            Self::NotFound(_) => ErrorKind::NotFound,
+            Self::RateLimited => ErrorKind::RateLimited,
+            Self::Status { .. } => ErrorKind::Other,
+            Self::Transport(_) => ErrorKind::Transport,
+            Self::Decode(_) => ErrorKind::Decode,
+        }
+    }
+
+    /// See [`ErrorKind::is_fatal`].
+    pub fn is_fatal(&self) -> bool {
+        self.kind().is_fatal()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "Up Bank rejected the access token"),
+            Self::NotFound(resource) => write!(f, "Up Bank {resource} not found"),
+            Self::RateLimited => write!(f, "Up Bank is rate limiting requests"),
+            Self::Status { status, message } => {
+                write!(f, "Up Bank returned {status}: {message}")
+            }
+            Self::Transport(e) => write!(f, "Up Bank request failed: {e}"),
+            Self::Decode(e) => write!(f, "Up Bank response could not be decoded: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Self::Decode(e.to_string())
+        } else {
+            Self::Transport(SendError::Request(e))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ErrorBody {
+    #[serde(default)]
+    errors: Vec<ErrorObject>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorObject {
+    title: String,
+    detail: String,
+}
+
+/// Returns the response when it was successful, otherwise the error it describes.
+pub(super) async fn check_response(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let path = response.url().path().to_string();
+
+    let text = response.text().await?;
+    let body: ErrorBody = serde_json::from_str(&text).unwrap_or_default();
+    let message = if body.errors.is_empty() {
+        text
+    } else {
+        body.errors
+            .iter()
+            .map(|f| format!("{}: {}", f.title, f.detail))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    Err(match ErrorKind::of_status(status) {
+        ErrorKind::Unauthorized => Error::Unauthorized,
+        ErrorKind::NotFound => Error::NotFound(path),
+        ErrorKind::RateLimited => Error::RateLimited,
+        _ => Error::Status { status, message },
+    })
+}
+
+/// Checks the response and decodes its body.
+pub(super) async fn decode<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T> {
+    Ok(check_response(response).await?.json::<T>().await?)
+}
@@ -1,4 +1,6 @@
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct Links {
@@ -24,11 +26,75 @@ pub struct Tags {
     pub links: Option<Links>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
-pub struct MoneyObject {
+/// An amount of money as Up Bank reports it, the value is an exact decimal so arithmetic on it
+/// never loses cents.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Money {
     #[serde(rename = "currencyCode")]
     pub currency_code: String,
-    pub value: String,
+    pub value: Decimal,
     #[serde(rename = "valueInBaseUnits")]
     pub value_in_base_units: i64,
 }
+
+impl Money {
+    pub fn is_negative(&self) -> bool {
+        self.value.is_sign_negative() && !self.value.is_zero()
+    }
+
+    /// The amount without its sign, Firefly records the direction through the transaction type.
+    pub fn magnitude(&self) -> Decimal {
+        self.value.abs()
+    }
+}
+
+// The value is hashed as text, the way Up Bank sends it, so legacy hashes stay the same.
+impl Hash for Money {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.currency_code.hash(state);
+        self.value.to_string().hash(state);
+        self.value_in_base_units.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(value: &str) -> Money {
+        serde_json::from_value(serde_json::json!({
+            "currencyCode": "AUD",
+            "value": value,
+            "valueInBaseUnits": 0
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn magnitude_drops_the_sign() {
+        assert_eq!(money("-4.50").magnitude().to_string(), "4.50");
+        assert_eq!(money("12.05").magnitude().to_string(), "12.05");
+    }
+
+    #[test]
+    fn negative_means_money_left_the_account() {
+        assert!(money("-0.01").is_negative());
+        assert!(!money("0.01").is_negative());
+        assert!(!money("0.00").is_negative());
+        assert!(!money("-0.00").is_negative());
+    }
+
+    #[test]
+    fn round_trips_exactly_as_up_bank_sends_it() {
+        let json = serde_json::json!({
+            "currencyCode": "AUD",
+            "value": "-1234.50",
+            "valueInBaseUnits": -123450
+        });
+        let money: Money = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(money.value, Decimal::new(-123450, 2));
+        assert_eq!(money.value.to_string(), "-1234.50");
+        assert_eq!(serde_json::to_value(&money).unwrap(), json);
+    }
+}
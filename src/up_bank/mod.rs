@@ -1,33 +1,67 @@
-use chrono::{Local, NaiveDateTime, TimeZone, Utc};
-use color_eyre::eyre::{eyre, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use color_eyre::eyre::eyre;
 use reqwest::header;
 
-use self::{accounts::Account, categories::Categorie, tags::Tag, transactions::Transaction};
+use crate::{config::HttpSettings, http_client::HttpClient};
+
+use self::{
+    accounts::Account,
+    categories::Categorie,
+    error::{check_response, decode, Result},
+    tags::Tag,
+    transactions::Transaction,
+    webhooks::{Webhook, WebhookDeliveryLog, WebhookEvent},
+};
 
 pub mod accounts;
 pub mod categories;
+pub mod error;
 pub mod general;
 pub mod pagination;
 pub mod tags;
 pub mod transactions;
+pub mod webhooks;
 
 #[derive(Debug, Clone)]
 pub struct UpBank {
-    client: reqwest::Client,
+    client: HttpClient,
+    base_url: String,
     pub accounts: Vec<Account>,
     pub categories: Vec<Categorie>,
     pub tags: Vec<Tag>,
 }
 
-fn generate_url(path: &str) -> String {
-    format!("https://api.up.com.au/api/v1/{path}")
+fn generate_url(base: &str, path: &str) -> String {
+    format!("{}/{path}", base.trim_end_matches('/'))
+}
+
+/// The exact times used for `filter[since]` and `filter[until]` when fetching transactions, dates
+/// are anchored to the current local time of day.
+pub fn date_filter_bounds(
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> (Option<DateTime<Local>>, Option<DateTime<Local>>) {
+    let time = Utc::now().naive_local().time();
+    let to_local = |date: NaiveDate| {
+        Local
+            .from_local_datetime(&NaiveDateTime::new(date, time))
+            .unwrap()
+    };
+    (start_date.map(to_local), end_date.map(to_local))
 }
 
 impl UpBank {
-    pub fn create(access_token: String) -> Result<Self> {
+    pub fn create(
+        access_token: String,
+        base_url: String,
+        http_settings: &HttpSettings,
+    ) -> color_eyre::Result<Self> {
         if access_token.is_empty() {
             return Err(eyre!("Up Bank access token was not set"));
         }
+        if base_url.is_empty() {
+            return Err(eyre!("Up Bank base url was empty"));
+        }
 
         let access_token = format!("Bearer {access_token}",);
         let mut auth_value = header::HeaderValue::from_str(access_token.as_str())?;
@@ -41,31 +75,32 @@ impl UpBank {
 
         headers.insert(header::AUTHORIZATION, auth_value);
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
+        let client = HttpClient::create(
+            "Up Bank",
+            reqwest::Client::builder().default_headers(headers),
+            http_settings,
+        )?;
 
         Ok(Self {
             client,
+            base_url,
             accounts: Vec::new(),
             categories: Vec::new(),
             tags: Vec::new(),
         })
     }
 
+    /// How long until requests are sent to Up Bank again after repeated failures.
+    pub fn circuit_open_for(&self) -> Option<std::time::Duration> {
+        self.client.circuit_open_for()
+    }
+
     pub async fn ping(&self) -> Result<()> {
-        let request_url = generate_url("util/ping");
+        let request_url = generate_url(&self.base_url, "util/ping");
         let response = self.client.get(request_url).send().await?;
 
-        if response.status() == 200 {
-            Ok(())
-        } else {
-            Err(eyre!(
-                "ping failed with code: {}, resp: {:?}",
-                response.status(),
-                response.headers()
-            ))
-        }
+        check_response(response).await?;
+        Ok(())
     }
 
     pub async fn populate_data(&mut self) -> Result<()> {
@@ -78,16 +113,11 @@ impl UpBank {
     pub async fn get_accounts(&self) -> Result<Vec<Account>> {
         let mut accounts: Vec<Account> = vec![];
 
-        let mut request_url = generate_url("accounts");
+        let mut request_url = generate_url(&self.base_url, "accounts");
 
         loop {
-            let mut account_data = self
-                .client
-                .get(request_url)
-                .send()
-                .await?
-                .json::<accounts::AccountsResponse>()
-                .await?;
+            let response = self.client.get(request_url).send().await?;
+            let mut account_data = decode::<accounts::AccountsResponse>(response).await?;
 
             accounts.append(&mut account_data.data);
 
@@ -104,40 +134,38 @@ impl UpBank {
         &self,
         start_date: Option<chrono::naive::NaiveDate>,
         end_date: Option<chrono::naive::NaiveDate>,
+    ) -> Result<Vec<Transaction>> {
+        let (since, until) = date_filter_bounds(start_date, end_date);
+        self.get_transactions_between(since, until).await
+    }
+
+    /// Fetches every transaction created inside the exact bounds returned by
+    /// [`date_filter_bounds`], for callers that need to reuse the same bounds afterwards.
+    pub async fn get_transactions_between(
+        &self,
+        since: Option<DateTime<Local>>,
+        until: Option<DateTime<Local>>,
     ) -> Result<Vec<Transaction>> {
         let mut transactions: Vec<Transaction> = vec![];
 
         let mut params: Vec<(String, String)> = vec![];
 
-        let time = Utc::now().naive_local().time();
-
-        if let Some(date) = start_date {
-            let date_time = Local
-                .from_local_datetime(&NaiveDateTime::new(date, time))
-                .unwrap();
+        if let Some(date_time) = since {
             let date_filter = ("filter[since]".to_string(), date_time.to_rfc3339());
             params.push(date_filter);
         }
 
-        if let Some(date) = end_date {
-            let date_time = Local
-                .from_local_datetime(&NaiveDateTime::new(date, time))
-                .unwrap();
+        if let Some(date_time) = until {
             let date_filter = ("filter[until]".to_string(), date_time.to_rfc3339());
             params.push(date_filter);
         }
 
-        let mut request_url = generate_url("transactions");
+        let mut request_url = generate_url(&self.base_url, "transactions");
 
         loop {
-            let mut transaction_data = self
-                .client
-                .get(request_url)
-                .query(&params)
-                .send()
-                .await?
-                .json::<transactions::TransactionResponse>()
-                .await?;
+            let response = self.client.get(request_url).query(&params).send().await?;
+            let mut transaction_data =
+                decode::<transactions::TransactionResponse>(response).await?;
 
             transactions.append(&mut transaction_data.data);
 
@@ -150,19 +178,22 @@ impl UpBank {
         Ok(transactions)
     }
 
+    pub async fn get_transaction(&self, id: &str) -> Result<Transaction> {
+        let request_url = generate_url(&self.base_url, &format!("transactions/{id}"));
+        let response = self.client.get(request_url).send().await?;
+
+        let transaction = decode::<transactions::SingleTransactionResponse>(response).await?;
+        Ok(transaction.data)
+    }
+
     pub async fn get_all_tags(&self) -> Result<Vec<tags::Tag>> {
         let mut tags: Vec<tags::Tag> = vec![];
 
-        let mut request_url = generate_url("tags");
+        let mut request_url = generate_url(&self.base_url, "tags");
 
         loop {
-            let mut tag_data = self
-                .client
-                .get(request_url)
-                .send()
-                .await?
-                .json::<tags::TagsResponse>()
-                .await?;
+            let response = self.client.get(request_url).send().await?;
+            let mut tag_data = decode::<tags::TagsResponse>(response).await?;
 
             tags.append(&mut tag_data.data);
 
@@ -176,15 +207,94 @@ impl UpBank {
     }
 
     pub async fn get_all_categories(&self) -> Result<Vec<categories::Categorie>> {
-        let request_url = generate_url("categories");
-        let catergorie_data = self
+        let request_url = generate_url(&self.base_url, "categories");
+        let response = self.client.get(request_url).send().await?;
+        let catergorie_data = decode::<categories::CategoriesResponse>(response).await?;
+
+        Ok(catergorie_data.data)
+    }
+
+    pub async fn get_all_webhooks(&self) -> Result<Vec<Webhook>> {
+        let mut webhooks: Vec<Webhook> = vec![];
+
+        let mut request_url = generate_url(&self.base_url, "webhooks");
+
+        loop {
+            let response = self.client.get(request_url).send().await?;
+            let mut webhook_data = decode::<webhooks::WebhooksResponse>(response).await?;
+
+            webhooks.append(&mut webhook_data.data);
+
+            match webhook_data.links.next {
+                Some(next_url) => request_url = next_url,
+                None => break,
+            }
+        }
+
+        Ok(webhooks)
+    }
+
+    /// Registers a new webhook, the returned webhook is the only place Up Bank exposes its
+    /// secret key.
+    pub async fn create_webhook(&self, url: &str, description: Option<String>) -> Result<Webhook> {
+        let payload = webhooks::CreateWebhookRequest {
+            data: webhooks::CreateWebhookData {
+                attributes: webhooks::CreateWebhookAttributes {
+                    url: url.to_string(),
+                    description,
+                },
+            },
+        };
+        let response = self
             .client
-            .get(request_url)
+            .post(generate_url(&self.base_url, "webhooks"))
+            .json(&payload)
             .send()
-            .await?
-            .json::<categories::CategoriesResponse>()
             .await?;
 
-        Ok(catergorie_data.data)
+        Ok(decode::<webhooks::WebhookResponse>(response).await?.data)
+    }
+
+    pub async fn delete_webhook(&self, id: &str) -> Result<()> {
+        let response = self
+            .client
+            .delete(generate_url(&self.base_url, &format!("webhooks/{id}")))
+            .send()
+            .await?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+
+    pub async fn ping_webhook(&self, id: &str) -> Result<WebhookEvent> {
+        let response = self
+            .client
+            .post(generate_url(&self.base_url, &format!("webhooks/{id}/ping")))
+            .send()
+            .await?;
+
+        Ok(decode::<webhooks::WebhookEventResponse>(response)
+            .await?
+            .data)
+    }
+
+    pub async fn get_webhook_logs(&self, id: &str) -> Result<Vec<WebhookDeliveryLog>> {
+        let mut logs: Vec<WebhookDeliveryLog> = vec![];
+
+        let mut request_url = generate_url(&self.base_url, &format!("webhooks/{id}/logs"));
+
+        loop {
+            let response = self.client.get(request_url).send().await?;
+            let mut log_data = decode::<webhooks::WebhookLogsResponse>(response).await?;
+
+            logs.append(&mut log_data.data);
+
+            match log_data.links.next {
+                Some(next_url) => request_url = next_url,
+                None => break,
+            }
+        }
+
+        Ok(logs)
     }
 }
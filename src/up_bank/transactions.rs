@@ -1,8 +1,10 @@
 use super::{
-    general::{self, MoneyObject},
+    general::{self, Money},
     pagination::Pagination,
 };
+use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionResponse {
@@ -10,6 +12,11 @@ pub struct TransactionResponse {
     pub links: Pagination,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SingleTransactionResponse {
+    pub data: Transaction,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct Transaction {
     #[serde(rename = "type")]
@@ -20,9 +27,9 @@ pub struct Transaction {
     pub links: Option<general::Links>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Attributes {
-    pub status: String, // Type Incorrect (enum)
+    pub status: TransactionStatus,
     #[serde(rename = "rawText")]
     pub raw_text: Option<String>,
     pub description: String,
@@ -34,15 +41,57 @@ pub struct Attributes {
     #[serde(rename = "roundUp")]
     pub round_up: Option<RoundUpObject>,
     pub cashback: Option<Cashback>,
-    pub amount: MoneyObject,
+    pub amount: Money,
     #[serde(rename = "foreignAmount")]
-    pub foreign_amount: Option<MoneyObject>,
+    pub foreign_amount: Option<Money>,
     #[serde(rename = "cardPurchaseMethod")]
     pub card_purchase_method: Option<CardPurchaseMethod>,
     #[serde(rename = "settledAt")]
-    pub settled_at: Option<String>, // Type Incorrect (datetime)
+    pub settled_at: Option<DateTime<FixedOffset>>,
     #[serde(rename = "createdAt")]
-    pub created_at: String, // Type Incorrect (datetime)
+    pub created_at: DateTime<FixedOffset>,
+}
+
+// Timestamps are hashed in the RFC 3339 form Up Bank sends them in, so legacy hashes stay the same.
+impl Hash for Attributes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.status.hash(state);
+        self.raw_text.hash(state);
+        self.description.hash(state);
+        self.message.hash(state);
+        self.is_categorizable.hash(state);
+        self.hold_info.hash(state);
+        self.round_up.hash(state);
+        self.cashback.hash(state);
+        self.amount.hash(state);
+        self.foreign_amount.hash(state);
+        self.card_purchase_method.hash(state);
+        self.settled_at.map(|f| f.to_rfc3339()).hash(state);
+        self.created_at.to_rfc3339().hash(state);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TransactionStatus {
+    Held,
+    Settled,
+}
+
+impl TransactionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Held => "HELD",
+            Self::Settled => "SETTLED",
+        }
+    }
+}
+
+// Hashed as the raw status string so that hashes written while status was a `String` still match.
+impl Hash for TransactionStatus {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
@@ -83,22 +132,22 @@ pub struct CategoryLinks {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct HoldInfoObject {
-    pub amount: MoneyObject,
+    pub amount: Money,
     #[serde(rename = "foreignAmount")]
-    pub foreign_amount: Option<MoneyObject>,
+    pub foreign_amount: Option<Money>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct RoundUpObject {
-    pub amount: MoneyObject,
+    pub amount: Money,
     #[serde(rename = "boostPortion")]
-    pub boost_portion: Option<MoneyObject>,
+    pub boost_portion: Option<Money>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct Cashback {
     pub description: String,
-    pub amount: MoneyObject,
+    pub amount: Money,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
@@ -107,3 +156,50 @@ pub struct CardPurchaseMethod {
     #[serde(rename = "cardNumberSuffix")]
     pub card_number_suffix: Option<String>,
 }
+
+/// Builds Up Bank transactions for tests, shaped like the responses of the transactions endpoint.
+#[cfg(test)]
+pub mod fixtures {
+    use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+    use super::Transaction;
+
+    /// A settled card purchase from the `acc1` transaction account, `amount` is given the way Up
+    /// Bank sends it, e.g. `"-4.50"`.
+    pub fn transaction(id: &str, description: &str, amount: &str) -> Transaction {
+        let value: Decimal = amount.parse().unwrap();
+        let base_units = (value * Decimal::from(100)).to_i64().unwrap();
+        serde_json::from_value(serde_json::json!({
+            "type": "transactions",
+            "id": id,
+            "attributes": {
+                "status": "SETTLED",
+                "rawText": null,
+                "description": description,
+                "message": null,
+                "isCategorizable": true,
+                "holdInfo": null,
+                "roundUp": null,
+                "cashback": null,
+                "amount": {
+                    "currencyCode": "AUD",
+                    "value": amount,
+                    "valueInBaseUnits": base_units
+                },
+                "foreignAmount": null,
+                "cardPurchaseMethod": null,
+                "settledAt": "2023-02-01T10:00:00+10:00",
+                "createdAt": "2023-02-01T09:30:00+10:00"
+            },
+            "relationships": {
+                "account": { "data": { "type": "accounts", "id": "acc1" } },
+                "transferAccount": { "data": null },
+                "category": { "data": null },
+                "parentCategory": { "data": null },
+                "tags": { "data": [] }
+            },
+            "links": { "self": format!("https://api.up.com.au/api/v1/transactions/{id}") }
+        }))
+        .unwrap()
+    }
+}
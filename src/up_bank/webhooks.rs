@@ -0,0 +1,139 @@
+use super::{
+    general::{self},
+    pagination::Pagination,
+};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEventResponse {
+    pub data: WebhookEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub id: String,
+    pub attributes: EventAttributes,
+    pub relationships: EventRelationships,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WebhookEventType {
+    TransactionCreated,
+    TransactionSettled,
+    TransactionDeleted,
+    Ping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventAttributes {
+    #[serde(rename = "eventType")]
+    pub event_type: WebhookEventType,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventRelationships {
+    pub webhook: WebhookLink,
+    pub transaction: Option<TransactionLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookLink {
+    pub data: general::Data,
+    pub links: Option<general::LinksRelated>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionLink {
+    pub data: general::Data,
+    pub links: Option<general::LinksRelated>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhooksResponse {
+    pub data: Vec<Webhook>,
+    pub links: Pagination,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookResponse {
+    pub data: Webhook,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Webhook {
+    #[serde(rename = "type")]
+    pub webhook_type: String,
+    pub id: String,
+    pub attributes: WebhookAttributes,
+    pub links: Option<general::Links>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookAttributes {
+    pub url: String,
+    pub description: Option<String>,
+    // Only returned by Up Bank when the webhook is created.
+    #[serde(rename = "secretKey")]
+    pub secret_key: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateWebhookRequest {
+    pub data: CreateWebhookData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateWebhookData {
+    pub attributes: CreateWebhookAttributes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateWebhookAttributes {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookLogsResponse {
+    pub data: Vec<WebhookDeliveryLog>,
+    pub links: Pagination,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookDeliveryLog {
+    #[serde(rename = "type")]
+    pub log_type: String,
+    pub id: String,
+    pub attributes: DeliveryLogAttributes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryLogAttributes {
+    pub request: DeliveryRequest,
+    pub response: Option<DeliveryResponse>,
+    #[serde(rename = "deliveryStatus")]
+    pub delivery_status: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryRequest {
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryResponse {
+    #[serde(rename = "statusCode")]
+    pub status_code: i64,
+    pub body: String,
+}
@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use color_eyre::eyre::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::migrator::Migrator;
+use crate::up_bank::{
+    self,
+    webhooks::{WebhookEvent, WebhookEventResponse, WebhookEventType},
+};
+
+const SIGNATURE_HEADER: &str = "X-Up-Authenticity-Signature";
+
+/// A verified event waiting to be processed, the receiver reports back through `processed` whether
+/// it was imported so the delivery can be acknowledged.
+pub struct Delivery {
+    pub event: WebhookEvent,
+    pub processed: oneshot::Sender<bool>,
+}
+
+#[derive(Clone)]
+struct WebhookState {
+    secret_keys: Arc<Vec<String>>,
+    sender: mpsc::Sender<Delivery>,
+}
+
+/// Builds the router that receives Up Bank webhook deliveries. Verified events are forwarded to
+/// `sender` so that they are processed one at a time, and the delivery is only acknowledged once
+/// the event was processed. A failure is answered with an error so Up Bank delivers it again. A
+/// delivery is accepted when it was signed by any of the given secret keys.
+pub fn router(secret_keys: Vec<String>, sender: mpsc::Sender<Delivery>) -> Router {
+    let state = WebhookState {
+        secret_keys: Arc::new(secret_keys),
+        sender,
+    };
+    Router::new()
+        .route("/", post(receive_event))
+        .route("/webhook", post(receive_event))
+        .with_state(state)
+}
+
+async fn receive_event(
+    State(state): State<WebhookState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let signature = match headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(signature) => signature,
+        None => {
+            warn!("Webhook received without a {} header", SIGNATURE_HEADER);
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+
+    if !state
+        .secret_keys
+        .iter()
+        .any(|secret_key| verify_signature(secret_key, &body, signature))
+    {
+        warn!("Webhook received with an invalid signature, ignoring");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let event = match serde_json::from_slice::<WebhookEventResponse>(&body) {
+        Ok(event) => event.data,
+        Err(e) => {
+            warn!("Failed to parse webhook event, error: {}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    debug!(
+        "Webhook event({}) received: {:?}",
+        event.id, event.attributes.event_type
+    );
+    let (processed, result) = oneshot::channel();
+    if state
+        .sender
+        .send(Delivery { event, processed })
+        .await
+        .is_err()
+    {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    match result.await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::INTERNAL_SERVER_ERROR,
+        // The listener is shutting down.
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// Checks the HMAC SHA-256 signature Up Bank sends with every webhook delivery against the raw
+/// request body.
+pub fn verify_signature(secret_key: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature.trim()) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret_key.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Fetches the transaction an event refers to and pushes it through the migrator.
+pub async fn handle_event(
+    event: WebhookEvent,
+    up_bank: &up_bank::UpBank,
+    migrator: &mut Migrator,
+) -> Result<()> {
+    let transaction_id = match &event.relationships.transaction {
+        Some(transaction) => transaction.data.id.clone(),
+        None => {
+            info!(
+                "Webhook event({}) {:?} received",
+                event.id, event.attributes.event_type
+            );
+            return Ok(());
+        }
+    };
+
+    match event.attributes.event_type {
+        WebhookEventType::TransactionCreated | WebhookEventType::TransactionSettled => {
+            let transaction = up_bank.get_transaction(&transaction_id).await?;
+            migrator.migrate_single_transaction(&transaction).await?;
+        }
+        WebhookEventType::TransactionDeleted => {
+            info!("Transaction({}) was deleted in Up Bank", transaction_id);
+            migrator.remove_deleted_transaction(&transaction_id).await?;
+            migrator.print_plan();
+        }
+        WebhookEventType::Ping => {
+            info!("Webhook ping({}) received", event.id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET_KEY: &str = "secret";
+
+    fn sign(secret_key: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn ping_body() -> Bytes {
+        Bytes::from(
+            serde_json::json!({
+                "data": {
+                    "type": "webhook-events",
+                    "id": "event1",
+                    "attributes": {
+                        "eventType": "PING",
+                        "createdAt": "2023-02-01T09:30:00+10:00"
+                    },
+                    "relationships": {
+                        "webhook": {
+                            "data": { "type": "webhooks", "id": "webhook1" }
+                        }
+                    }
+                }
+            })
+            .to_string(),
+        )
+    }
+
+    fn state() -> (WebhookState, mpsc::Receiver<Delivery>) {
+        let (sender, receiver) = mpsc::channel(1);
+        let state = WebhookState {
+            secret_keys: Arc::new(vec!["other".to_string(), SECRET_KEY.to_string()]),
+            sender,
+        };
+        (state, receiver)
+    }
+
+    fn signed_headers(signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        let body = ping_body();
+        assert!(verify_signature(
+            SECRET_KEY,
+            &body,
+            &sign(SECRET_KEY, &body)
+        ));
+    }
+
+    #[test]
+    fn tampered_body_is_rejected() {
+        let body = ping_body();
+        let signature = sign(SECRET_KEY, &body);
+        let mut tampered = body.to_vec();
+        tampered[0] = b' ';
+        assert!(!verify_signature(SECRET_KEY, &tampered, &signature));
+    }
+
+    #[test]
+    fn wrong_secret_is_rejected() {
+        let body = ping_body();
+        assert!(!verify_signature(SECRET_KEY, &body, &sign("wrong", &body)));
+    }
+
+    #[test]
+    fn malformed_signature_is_rejected() {
+        assert!(!verify_signature(SECRET_KEY, &ping_body(), "not hex"));
+    }
+
+    #[tokio::test]
+    async fn missing_header_is_unauthorized() {
+        let (state, _receiver) = state();
+        let status = receive_event(State(state), HeaderMap::new(), ping_body()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn invalid_signature_is_unauthorized() {
+        let (state, _receiver) = state();
+        let body = ping_body();
+        let headers = signed_headers(&sign("wrong", &body));
+        let status = receive_event(State(state), headers, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn deliver(processed: bool) -> StatusCode {
+        let (state, mut receiver) = state();
+        let body = ping_body();
+        let headers = signed_headers(&sign(SECRET_KEY, &body));
+        let processor = tokio::spawn(async move {
+            let delivery = receiver.recv().await.unwrap();
+            assert_eq!(delivery.event.id, "event1");
+            delivery.processed.send(processed).unwrap();
+        });
+        let status = receive_event(State(state), headers, body).await;
+        processor.await.unwrap();
+        status
+    }
+
+    #[tokio::test]
+    async fn processed_event_is_acknowledged() {
+        assert_eq!(deliver(true).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn failed_event_is_not_acknowledged() {
+        assert_eq!(deliver(false).await, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn dropped_event_is_not_acknowledged() {
+        let (state, mut receiver) = state();
+        let body = ping_body();
+        let headers = signed_headers(&sign(SECRET_KEY, &body));
+        tokio::spawn(async move {
+            drop(receiver.recv().await);
+        });
+        let status = receive_event(State(state), headers, body).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}